@@ -3,7 +3,12 @@ use std::{
     collections::{HashMap, HashSet},
 };
 
-use crate::{CodeBlock, Expression, FileElement, Instruction};
+use cythanc::closest;
+
+use crate::{
+    ir_name, module_of, qualify, BooleanExpression, BooleanTest, CodeBlock, Expression,
+    FileElement, Instruction, Operator, Overflow, SourceError, Span, Type,
+};
 
 use anyhow::*;
 
@@ -14,87 +19,486 @@ pub struct CompilationContext<'a> {
     pub asm_file: Vec<Cow<'a, str>>,
     pub current_expression_out_expr: Cow<'a, str>,
     pub current_function_context: Option<FunctionContext<'a>>,
-    pub loops: Vec<usize>,
-    pub existing_vars: HashSet<Cow<'a, str>>,
+    /// Number of every loop being lowered, with the label `continue` jumps to.
+    pub loops: Vec<(usize, String)>,
+    /// Variables visible from the code being lowered.
+    pub scopes: Scopes,
+    /// Span of the statement being lowered, recorded by [`add`](Self::add).
+    pub span: Span,
+    /// Span of the statement every line of `asm_file` comes from.
+    pub origins: Vec<Span>,
+    /// Functions that can call themselves back, compiled with a call stack.
+    pub recursive: HashSet<String>,
+    /// Value of every constant.
+    pub constants: HashMap<String, u8>,
+    /// IR name of every static.
+    pub statics: HashMap<String, String>,
+    /// What the `u4` arithmetic being lowered does on overflow.
+    pub overflow: Overflow,
 }
 
 #[derive(Debug)]
 pub struct FunctionContext<'a> {
-    arguments: HashSet<Cow<'a, str>>,
     name: Cow<'a, str>,
 }
 
+/// Variables of the function being lowered, in nested block scopes.
+///
+/// Locals are named `function.name` in the IR (`main.a`), followed by a number when a block
+/// declares a name already used by another variable of the function.
+#[derive(Debug, Default)]
+pub struct Scopes {
+    function: String,
+    /// Source name to IR name of the variables of every open block, innermost last.
+    blocks: Vec<HashMap<String, String>>,
+    /// IR names given in the function.
+    used: HashSet<String>,
+    /// Names declared in blocks that have ended, to explain why they can't be used.
+    ended: HashSet<String>,
+    /// Type of the variables of the function declared with one, by IR name, the others being
+    /// `u4` values.
+    types: HashMap<String, Type>,
+}
+
+impl Scopes {
+    /// Scopes of `function`, whose arguments are the IR parameters `$name`.
+    pub fn new(function: &str, arguments: &[Cow<str>]) -> Self {
+        Self {
+            function: function.to_owned(),
+            blocks: vec![arguments
+                .iter()
+                .map(|x| (x.to_string(), format!("${}", x)))
+                .collect()],
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self) {
+        self.blocks.push(HashMap::new());
+    }
+
+    pub fn pop(&mut self) {
+        if let Some(block) = self.blocks.pop() {
+            self.ended.extend(block.into_keys());
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.blocks
+            .iter()
+            .rev()
+            .find_map(|x| x.get(name))
+            .map(|x| x.as_str())
+    }
+
+    /// Declares `name` in the innermost block and returns its IR name.
+    fn declare(&mut self, name: &str) -> String {
+        let mut ir = format!("{}.{}", self.function, name);
+        let mut count = 1;
+        while self.used.contains(&ir) {
+            count += 1;
+            ir = format!("{}.{}.{}", self.function, name, count);
+        }
+        self.used.insert(ir.clone());
+        self.blocks
+            .last_mut()
+            .expect("a function always has a scope")
+            .insert(name.to_owned(), ir.clone());
+        ir
+    }
+
+    /// Type of the variable of IR name `ir`.
+    fn type_of(&self, ir: &str) -> Type {
+        self.types.get(ir).copied().unwrap_or(Type::Unsigned(1))
+    }
+
+    /// Whether the variable of IR name `ir` is an array.
+    fn is_array(&self, ir: &str) -> bool {
+        matches!(self.type_of(ir), Type::Array(_))
+    }
+
+    /// Error for the use of `name`, which isn't visible, `globals` being the names visible from
+    /// every function.
+    fn not_found<'a>(
+        &'a self,
+        name: &str,
+        span: Span,
+        globals: impl Iterator<Item = &'a str>,
+    ) -> SourceError {
+        let error = SourceError::new(
+            span,
+            format!("cannot find variable `{}` in this scope", name),
+        );
+        if self.ended.contains(name) {
+            return error.with_note(format!(
+                "note: `{}` is declared in a block that has ended",
+                name
+            ));
+        }
+        let visible = self
+            .blocks
+            .iter()
+            .flat_map(|x| x.keys().map(|x| x.as_str()))
+            .chain(globals);
+        match closest(name, visible) {
+            Some(e) => error.with_note(format!("help: did you mean `{}`?", e)),
+            None => error.with_note(format!("help: assign it first, as in `{} = 0;`", name)),
+        }
+    }
+}
+
 impl<'a> CompilationContext<'a> {
-    pub fn check_func(&self, fnname: &str, expressions: &Vec<Expression>) -> Result<()> {
-        let args = self
-            .functions_refs
-            .get(fnname)
-            .map(|(_, x)| x)
-            .ok_or_else(|| anyhow!("Can't find function {}", fnname))?;
+    pub fn check_func(&self, fnname: &str, expressions: &[Expression], span: Span) -> Result<()> {
+        let args = match self.functions_refs.get(fnname) {
+            Some((_, e)) => e,
+            None => {
+                let error = SourceError::new(span, format!("can't find function `{}`", fnname));
+                return Err(
+                    match closest(fnname, self.functions_refs.keys().map(|x| x.as_str())) {
+                        Some(e) => error.with_note(format!("help: did you mean `{}`?", e)),
+                        None => error,
+                    }
+                    .into(),
+                );
+            }
+        };
         if args.len() != expressions.len() {
-            return Err(anyhow!("Invalid number of arguments"));
+            return Err(SourceError::new(
+                span,
+                format!(
+                    "`{}` takes {} argument(s) but {} were given",
+                    fnname,
+                    args.len(),
+                    expressions.len()
+                ),
+            )
+            .with_note(format!(
+                "note: `{}` is declared as `{}({})`",
+                fnname,
+                fnname,
+                args.join(", ")
+            ))
+            .into());
         }
         Ok(())
     }
 
+    /// Module of the function being lowered, empty for the compiled file.
+    fn module(&self) -> &str {
+        self.current_function_context
+            .as_ref()
+            .map(|x| module_of(&x.name))
+            .unwrap_or("")
+    }
+
+    /// Full name of the function `name` called from the function being lowered.
+    pub fn function(&self, name: &str) -> String {
+        qualify(self.module(), name, |x| self.functions_refs.contains_key(x))
+    }
+
+    /// Full name of the static or constant `name` used from the function being lowered.
+    fn global(&self, name: &str) -> String {
+        qualify(self.module(), name, |x| {
+            self.statics.contains_key(x) || self.constants.contains_key(x)
+        })
+    }
+
+    /// The output of the last expression as a variable, copying it to a temporary if it is a
+    /// number.
+    pub fn out_variable(&mut self) -> String {
+        let out = self.current_expression_out_expr.to_string();
+        if out.starts_with('&') {
+            self.copy_out()
+        } else {
+            out
+        }
+    }
+
+    /// IR name of the variable `name` assigned at `span`, declaring it as a `ty` in the current
+    /// block if it isn't visible.
+    pub fn variable(&mut self, name: &str, ty: Type, span: Span) -> Result<String> {
+        if let Some(e) = self.scopes.get(name) {
+            if self.scopes.is_array(e) {
+                return Err(array_as_number(name, span).into());
+            }
+            return Ok(e.to_owned());
+        }
+        let global = self.global(name);
+        if let Some(e) = self.statics.get(&global) {
+            return Ok(e.to_owned());
+        }
+        if self.constants.contains_key(&global) {
+            return Err(
+                SourceError::new(span, format!("cannot assign to constant `{}`", name))
+                    .with_note("help: declare it with `static` to make it a variable")
+                    .into(),
+            );
+        }
+        Ok(self.declare_typed(name, ty))
+    }
+
+    /// The value of `name` used at `span`: the IR name of a variable or the `&n` immediate of a
+    /// constant, locals hiding statics and constants.
+    pub fn resolve(&self, name: &str, span: Span) -> Result<String> {
+        if let Some(e) = self.scopes.get(name) {
+            if self.scopes.is_array(e) {
+                return Err(array_as_number(name, span).into());
+            }
+            return Ok(e.to_owned());
+        }
+        let global = self.global(name);
+        if let Some(e) = self.statics.get(&global) {
+            return Ok(e.to_owned());
+        }
+        match self.constants.get(&global) {
+            Some(e) => Ok(format!("&{}", e)),
+            None => {
+                let globals = self
+                    .statics
+                    .keys()
+                    .chain(self.constants.keys())
+                    .map(|x| x.as_str());
+                Err(self.scopes.not_found(name, span, globals).into())
+            }
+        }
+    }
+
+    /// IR name of the array `name` indexed with `index` at `span`, a constant index having to be
+    /// within its length.
+    pub fn array(&self, name: &str, index: &Expression, span: Span) -> Result<String> {
+        match self.scopes.get(name).map(|x| (x, self.scopes.type_of(x))) {
+            Some((ir, Type::Array(length))) => {
+                let length = length as u16;
+                let constant = match index {
+                    Expression::Number(e, _) => Some(*e),
+                    Expression::Variable(a, span) => self
+                        .resolve(a, *span)
+                        .ok()
+                        .and_then(|x| x.strip_prefix('&')?.parse().ok()),
+                    _ => None,
+                };
+                match constant {
+                    Some(e) if e >= length => {
+                        return Err(SourceError::new(
+                            index.span(),
+                            format!(
+                                "index {} is out of bounds of `{}`, a `[u4; {}]`",
+                                e, name, length
+                            ),
+                        )
+                        .with_note(format!(
+                            "note: its elements go from `{}[0]` to `{}[{}]`",
+                            name,
+                            name,
+                            length.saturating_sub(1)
+                        ))
+                        .into())
+                    }
+                    _ => return Ok(ir.to_owned()),
+                }
+            }
+            Some(_) => (),
+            None => {
+                self.resolve(name, span)?;
+            }
+        }
+        Err(
+            SourceError::new(span, format!("`{}` is not an array", name))
+                .with_note(format!(
+                    "note: only variables declared as `let {}: [u4; n];` can be indexed",
+                    name
+                ))
+                .into(),
+        )
+    }
+
+    /// Declares a new variable `name` of type `ty` in the current block and returns its IR name.
+    pub fn declare_typed(&mut self, name: &str, ty: Type) -> String {
+        let ir = self.scopes.declare(name);
+        self.scopes.types.insert(ir.clone(), ty);
+        match ty {
+            Type::Unsigned(1) | Type::Signed(1) => self.add(format!("let {} 0", ir)),
+            Type::Unsigned(e) | Type::Signed(e) => self.add(format!("wide {} {} 0", ir, e)),
+            Type::Array(e) => self.add(format!("array {} {}", ir, e)),
+        }
+        ir
+    }
+
+    /// Type of the value of `name`, a `u4` for anything but a typed local.
+    fn type_of(&self, name: &str) -> Type {
+        self.scopes
+            .get(name)
+            .map(|x| self.scopes.type_of(x))
+            .unwrap_or(Type::Unsigned(1))
+    }
+
+    /// Type of the variable `name` assigned `value`: its own if it is visible, otherwise the one
+    /// the value needs, the assignment declaring it.
+    fn assigned_type(&self, name: &str, value: &Expression) -> Type {
+        if self.scopes.get(name).is_some() {
+            return self.type_of(name);
+        }
+        let global = self.global(name);
+        if self.statics.contains_key(&global) || self.constants.contains_key(&global) {
+            Type::Unsigned(1)
+        } else {
+            value.ty(self)
+        }
+    }
+
+    /// Emits what a jump if `lower` is lower than `higher`, two values of type `ty`, needs and
+    /// returns the jump, missing its label.
+    pub fn lower_than(&mut self, lower: &str, higher: &str, ty: Type) -> String {
+        let width = ty.width();
+        let wide = if width == 1 { "" } else { "w" };
+        if !ty.is_signed() {
+            return format!("{}if_lt {} {}", wide, lower, higher);
+        }
+        // Adding half the range flips the sign bit, which orders signed values as unsigned ones.
+        let bias = if width == 1 {
+            "&8".to_owned()
+        } else {
+            self.wide_temporary(width, 1 << (4 * width - 1))
+        };
+        let [lower, higher] = [lower, higher].map(|x| {
+            let biased = if width == 1 {
+                let temporary = self.count();
+                self.add(format!("let TMP{} 0", temporary));
+                format!("TMP{}", temporary)
+            } else {
+                self.wide_temporary(width, 0)
+            };
+            self.add(format!("{}add {} {} {}", wide, biased, x, bias));
+            biased
+        });
+        format!("{}if_lt {} {}", wide, lower, higher)
+    }
+
+    /// A new wide temporary variable of `width` nibbles holding `value`.
+    pub fn wide_temporary(&mut self, width: u8, value: u16) -> String {
+        let temporary = self.count();
+        self.add(format!("wide TMP{} {} {}", temporary, width, value));
+        format!("TMP{}", temporary)
+    }
+
+    /// Declares a new variable `name` in the current block and returns its IR name.
+    pub fn declare(&mut self, name: &str) -> String {
+        let ir = self.scopes.declare(name);
+        self.add(format!("let {} 0", ir));
+        ir
+    }
+
+    /// Copies the output of the last expression to a new temporary variable, for values that
+    /// must not change while they are used.
+    pub fn copy_out(&mut self) -> String {
+        let temporary = self.count();
+        self.add(format!("let TMP{} 0", temporary));
+        self.add(format!(
+            "set TMP{} {}",
+            temporary, self.current_expression_out_expr
+        ));
+        format!("TMP{}", temporary)
+    }
+
+    /// Emits `jump` (an IR jump instruction missing its label) so that it goes to `label` when
+    /// taken if `taken` is true, and when not taken otherwise.
+    pub fn add_jump(&mut self, jump: String, label: &str, taken: bool) {
+        if taken {
+            self.add(format!("{} '{}", jump, label));
+        } else {
+            let skip = self.count();
+            self.add(format!("{} 'cond_skip{}", jump, skip));
+            self.add(format!("jump '{}", label));
+            self.add(format!("label 'cond_skip{}", skip));
+        }
+    }
+
     pub fn count(&mut self) -> usize {
         self.counter += 1;
         self.counter
     }
 
     pub fn add(&mut self, string: String) {
-        self.asm_file.push(Cow::Owned(string))
+        self.asm_file.push(Cow::Owned(string));
+        self.origins.push(self.span);
     }
 
     pub fn add_str(&'a mut self, string: &'a str) {
-        self.asm_file.push(Cow::Borrowed(string))
+        self.asm_file.push(Cow::Borrowed(string));
+        self.origins.push(self.span);
     }
 }
 
+/// Error for `name`, a value of type `found`, used at `span` where a `expected` is needed.
+fn mismatched(name: &str, found: Type, expected: Type, span: Span) -> SourceError {
+    let article = |x: Type| if x.is_signed() { "an" } else { "a" };
+    SourceError::new(
+        span,
+        format!(
+            "mismatched types: `{}` is {} `{}` where {} `{}` is expected",
+            name,
+            article(found),
+            found,
+            article(expected),
+            expected
+        ),
+    )
+}
+
+/// Error for the overflow mode `mode` applied at `span` to a value of type `ty`, if it can't be.
+fn check_overflow(mode: Overflow, ty: Type, span: Span) -> Result<()> {
+    if mode == Overflow::Wrapping || ty == Type::Unsigned(1) {
+        return Ok(());
+    }
+    Err(SourceError::new(
+        span,
+        format!("`{}` arithmetic can't be used on `{}` values", mode, ty),
+    )
+    .with_note("note: only `u4` arithmetic can be checked or saturating")
+    .into())
+}
+
+/// Error for the array `name` used as a number at `span`.
+fn array_as_number(name: &str, span: Span) -> SourceError {
+    SourceError::new(span, format!("`{}` is an array, not a number", name)).with_note(format!(
+        "help: use one of its elements, as in `{}[0]`",
+        name
+    ))
+}
+
 impl<'a> FileElement<'a> {
     pub fn compile(&'a self, context: &'a mut CompilationContext) -> Result<()> {
         match self {
-            FileElement::Function(a, b, c) => {
+            FileElement::Function(a, b, c, span) => {
+                context.span = *span;
+                let name = ir_name(a);
+                context.scopes = Scopes::new(&name, b);
                 if a == "main" {
                     context.current_function_context = None;
                     c.compile(context)?;
                 } else {
-                    context.add(format!("let {}_out 0", a));
-                    context.add(format!("func {} {}", a, b.join(" ")));
+                    context.add(format!("let {}_out 0", name));
+                    let header = if context.recursive.contains(a.as_ref()) {
+                        "rfunc"
+                    } else {
+                        "func"
+                    };
+                    context.add(format!("{} {} {}", header, name, b.join(" ")));
                     context.current_function_context = Some(FunctionContext {
-                        arguments: b
-                            .iter()
-                            .cloned()
-                            .map(|x| Cow::Owned(x.into_owned()))
-                            .collect(),
                         name: Cow::Owned(a.clone().into_owned()),
                     });
                     c.compile(context)?;
+                    context.span = *span;
                     context.add("end_func".to_owned());
-                    context.functions_refs.insert(
-                        a.clone().into_owned(),
-                        (
-                            false,
-                            b.iter()
-                                .cloned()
-                                .map(|x| Cow::Owned(x.into_owned()))
-                                .collect(),
-                        ),
-                    );
                 }
             }
-            FileElement::FunctionExtern(a, b) => {
-                context.functions_refs.insert(
-                    a.clone().into_owned(),
-                    (
-                        true,
-                        b.iter()
-                            .cloned()
-                            .map(|x| Cow::Owned(x.into_owned()))
-                            .collect(),
-                    ),
-                );
+            FileElement::Static(a, b, span) => {
+                context.span = *span;
+                context.add(format!("let {} {}", context.statics[a.as_ref()], b));
             }
+            FileElement::FunctionExtern(..)
+            | FileElement::Constant(..)
+            | FileElement::Import(..) => {}
         }
         Ok(())
     }
@@ -102,89 +506,282 @@ impl<'a> FileElement<'a> {
 
 impl CodeBlock<'_> {
     pub fn compile(&self, context: &mut CompilationContext) -> Result<()> {
+        context.scopes.push();
         for i in &self.code {
             i.compile(context)?
         }
+        context.scopes.pop();
         Ok(())
     }
 }
 
 impl<'a> Instruction<'a> {
     pub fn compile(&self, context: &mut CompilationContext) -> Result<()> {
+        let outer = std::mem::replace(&mut context.span, self.span());
+        self.lower(context)?;
+        context.span = outer;
+        Ok(())
+    }
+
+    fn lower(&self, context: &mut CompilationContext) -> Result<()> {
         match self {
             Instruction::Expression(a) => a.compile(context)?,
-            Instruction::If(a, b, c) => {
+            Instruction::If(a, b, c, _) => {
                 let current = context.count();
-                if matches!(a.2, Expression::Number(0)) {
-                    a.0.compile(context)?;
-                } else if matches!(a.0, Expression::Number(0)) {
-                    a.2.compile(context)?;
-                } else {
-                    Expression::FunctionCall(Cow::Borrowed("sub"), vec![a.0.clone(), a.2.clone()])
-                        .compile(context)?;
-                }
-                match a.1 {
-                    crate::BooleanTest::Equals => {
-                        context.add(format!(
-                            "if_0 {} 'if_true{}",
-                            context.current_expression_out_expr, current
-                        ));
-                        if let Some(e) = c {
-                            e.compile(context)?;
-                        }
-                        context.add(format!("jump 'if_end{}", current));
-                        context.add(format!("label 'if_true{}", current));
-                        b.compile(context)?;
-                        context.add(format!("label 'if_end{}", current));
-                    }
-                    crate::BooleanTest::NotEquals => {
-                        context.add(format!(
-                            "if_0 {} 'if_false{}",
-                            context.current_expression_out_expr, current
-                        ));
-                        if let Some(e) = c {
-                            e.compile(context)?;
-                        }
-                        context.add(format!("jump 'if_end{}", current));
-                        context.add(format!("label 'if_false{}", current));
-                        b.compile(context)?;
-                        context.add(format!("label 'if_end{}", current));
-                    }
+                a.compile_jump(context, &format!("if_false{}", current), false)?;
+                b.compile(context)?;
+                context.add(format!("jump 'if_end{}", current));
+                context.add(format!("label 'if_false{}", current));
+                if let Some(e) = c {
+                    e.compile(context)?;
                 }
+                context.add(format!("label 'if_end{}", current));
             }
-            Instruction::Loop(a) => {
+            Instruction::Loop(a, _) => {
                 let current_loop = context.count();
                 context.add(format!("label 'for{}", current_loop));
-                context.loops.push(current_loop);
+                context
+                    .loops
+                    .push((current_loop, format!("for{}", current_loop)));
                 a.compile(context)?;
                 context.loops.pop();
+                context.add(format!("jump 'for{}", current_loop));
+                context.add(format!("label 'for_end{}", current_loop));
+            }
+            Instruction::While(a, b, _) => {
+                let current_loop = context.count();
+                context.add(format!("label 'for{}", current_loop));
+                a.compile_jump(context, &format!("for_end{}", current_loop), false)?;
+                context
+                    .loops
+                    .push((current_loop, format!("for{}", current_loop)));
+                b.compile(context)?;
+                context.loops.pop();
+                context.add(format!("jump 'for{}", current_loop));
+                context.add(format!("label 'for_end{}", current_loop));
+            }
+            Instruction::For(a, b, c, d, _) => {
+                let ty = b.common(c, context);
+                let width = ty.width();
+                let (start, end) = if width == 1 {
+                    let start = b.compile_as(context, ty)?;
+                    c.compile_as(context, ty)?;
+                    (start, context.copy_out())
+                } else {
+                    let start = b.compile_as(context, ty)?;
+                    let end = c.compile_as(context, ty)?;
+                    let copy = context.wide_temporary(width, 0);
+                    context.add(format!("wset {} {}", copy, end));
+                    (start, copy)
+                };
+                let wide = if width == 1 { "" } else { "w" };
+                context.scopes.push();
+                let variable = context.declare_typed(a, ty);
+                context.add(format!("{}set {} {}", wide, variable, start));
+                let current_loop = context.count();
+                context.add(format!("label 'for{}", current_loop));
+                let jump = context.lower_than(&variable, &end, ty);
+                context.add_jump(jump, &format!("for_end{}", current_loop), false);
+                context
+                    .loops
+                    .push((current_loop, format!("for_next{}", current_loop)));
+                d.compile(context)?;
+                context.loops.pop();
+                context.add(format!("label 'for_next{}", current_loop));
+                context.add(format!("{}inc {}", wide, variable));
+                context.add(format!("jump 'for{}", current_loop));
+                context.add(format!("label 'for_end{}", current_loop));
+                context.scopes.pop();
+            }
+            Instruction::Repeat(a, b, _) => {
+                let ty = a.ty(context);
+                let width = ty.width();
+                let current_loop;
+                if width == 1 {
+                    a.compile(context)?;
+                    let counter = context.copy_out();
+                    current_loop = context.count();
+                    context.add(format!("label 'for{}", current_loop));
+                    context.add(format!("if_0 {} 'for_end{}", counter, current_loop));
+                    context.add(format!("dec {}", counter));
+                } else {
+                    let count = a.compile_as(context, ty)?;
+                    let counter = context.wide_temporary(width, 0);
+                    context.add(format!("wset {} {}", counter, count));
+                    let zero = context.wide_temporary(width, 0);
+                    current_loop = context.count();
+                    context.add(format!("label 'for{}", current_loop));
+                    let jump = format!("wif_lt {} {}", zero, counter);
+                    context.add_jump(jump, &format!("for_end{}", current_loop), false);
+                    context.add(format!("wdec {}", counter));
+                }
+                context
+                    .loops
+                    .push((current_loop, format!("for{}", current_loop)));
+                b.compile(context)?;
+                context.loops.pop();
+                context.add(format!("jump 'for{}", current_loop));
                 context.add(format!("label 'for_end{}", current_loop));
             }
-            Instruction::Return(a) => {
+            Instruction::Return(a, span) => {
                 let fnname = &context
                     .current_function_context
                     .as_ref()
-                    .ok_or_else(|| anyhow!("Can't use return outside of a function"))?
+                    .ok_or_else(|| {
+                        SourceError::new(*span, "can't use `return` outside of a function")
+                    })?
                     .name
                     .clone();
                 if let Some(a) = a {
                     a.compile(context)?;
                     context.add(format!(
                         "set {}_out {}",
-                        fnname, context.current_expression_out_expr
+                        ir_name(fnname),
+                        context.current_expression_out_expr
                     ));
                 }
                 context.add("ret".to_owned());
             }
-            Instruction::Assign(a, b) => {
+            Instruction::Assign(a, b, span) => {
+                let ty = context.assigned_type(a, b);
+                let value = b.compile_as(context, ty)?;
+                let variable = context.variable(a, ty, *span)?;
+                let wide = if ty.width() == 1 { "" } else { "w" };
+                context.add(format!("{}set {} {}", wide, variable, value));
+            }
+            Instruction::Let(a, ty, Some(b), _) => {
+                let value = b.compile_as(context, *ty)?;
+                let variable = context.declare_typed(a, *ty);
+                let wide = if ty.width() == 1 { "" } else { "w" };
+                context.add(format!("{}set {} {}", wide, variable, value));
+            }
+            Instruction::Let(a, ty, None, _) => {
+                context.declare_typed(a, *ty);
+            }
+            Instruction::Store(a, b, c, span) => {
+                let array = context.array(a, b, *span)?;
+                c.compile(context)?;
+                let value = context.current_expression_out_expr.clone();
                 b.compile(context)?;
-                context.add(format!("set {} {}", a, context.current_expression_out_expr));
+                context.add(format!(
+                    "store {} {} {}",
+                    array, context.current_expression_out_expr, value
+                ));
+            }
+            Instruction::Continue(span) => {
+                let (_, label) = context
+                    .loops
+                    .last()
+                    .ok_or_else(|| SourceError::new(*span, "`continue` outside of a loop"))?;
+                context.add(format!("jump '{}", label))
+            }
+            Instruction::Break(span) => {
+                let (current_loop, _) = context
+                    .loops
+                    .last()
+                    .ok_or_else(|| SourceError::new(*span, "`break` outside of a loop"))?;
+                context.add(format!("jump 'for_end{}", current_loop))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BooleanExpression<'_> {
+    /// Emits code jumping to `label` when the condition is `when`, and going on otherwise.
+    ///
+    /// `&&` and `||` only evaluate their right side when the left one does not decide.
+    pub fn compile_jump(
+        &self,
+        context: &mut CompilationContext,
+        label: &str,
+        when: bool,
+    ) -> Result<()> {
+        match self {
+            BooleanExpression::Test(a, test, b) if a.common(b, context).width() > 1 => {
+                let ty = a.common(b, context);
+                let a = a.compile_as(context, ty)?;
+                let b = b.compile_as(context, ty)?;
+                match test {
+                    BooleanTest::Equals | BooleanTest::NotEquals => {
+                        // Two numbers are equal when neither is lower than the other.
+                        if when == (*test == BooleanTest::Equals) {
+                            let skip = format!("cond_skip{}", context.count());
+                            context.add(format!("wif_lt {} {} '{}", a, b, skip));
+                            context.add(format!("wif_lt {} {} '{}", b, a, skip));
+                            context.add(format!("jump '{}", label));
+                            context.add(format!("label '{}", skip));
+                        } else {
+                            context.add(format!("wif_lt {} {} '{}", a, b, label));
+                            context.add(format!("wif_lt {} {} '{}", b, a, label));
+                        }
+                    }
+                    _ => {
+                        let (lower, higher, lower_than) = match test {
+                            BooleanTest::Lower => (a, b, true),
+                            BooleanTest::Greater => (b, a, true),
+                            BooleanTest::GreaterOrEqual => (a, b, false),
+                            _ => (b, a, false),
+                        };
+                        let jump = context.lower_than(&lower, &higher, ty);
+                        context.add_jump(jump, label, when == lower_than);
+                    }
+                }
+            }
+            BooleanExpression::Test(
+                a,
+                test @ (BooleanTest::Equals | BooleanTest::NotEquals),
+                b,
+            ) => {
+                if matches!(b, Expression::Number(0, _)) {
+                    a.compile(context)?;
+                } else if matches!(a, Expression::Number(0, _)) {
+                    b.compile(context)?;
+                } else {
+                    // The difference only tells whether the values are equal, so it wraps
+                    // whatever the overflow mode is.
+                    let result = context.count();
+                    a.compile(context)?;
+                    let left = context.current_expression_out_expr.clone();
+                    b.compile(context)?;
+                    context.add(format!("let TMP{} 0", result));
+                    context.add(format!(
+                        "sub TMP{} {} {}",
+                        result, left, context.current_expression_out_expr
+                    ));
+                    context.current_expression_out_expr = Cow::Owned(format!("TMP{}", result));
+                }
+                let value = context.out_variable();
+                let jump = format!("if_0 {}", value);
+                context.add_jump(jump, label, when == (*test == BooleanTest::Equals));
             }
-            Instruction::Continue => {
-                context.add(format!("jump 'for{}", context.loops.last().unwrap()))
+            BooleanExpression::Test(a, test, b) => {
+                let ty = a.common(b, context);
+                let a = a.compile_as(context, ty)?;
+                let b = b.compile_as(context, ty)?;
+                let (lower, higher, lower_than) = match test {
+                    BooleanTest::Lower => (a, b, true),
+                    BooleanTest::Greater => (b, a, true),
+                    BooleanTest::GreaterOrEqual => (a, b, false),
+                    _ => (b, a, false),
+                };
+                let jump = context.lower_than(&lower, &higher, ty);
+                context.add_jump(jump, label, when == lower_than);
             }
-            Instruction::Break => {
-                context.add(format!("jump 'for_end{}", context.loops.last().unwrap()))
+            BooleanExpression::Not(a) => a.compile_jump(context, label, !when)?,
+            BooleanExpression::And(a, b) | BooleanExpression::Or(a, b) => {
+                // `a && b` is true when both are and `a || b` false when both are, otherwise the
+                // left side alone may decide.
+                let both = matches!(self, BooleanExpression::And(..));
+                if when == both {
+                    let skip = format!("cond_skip{}", context.count());
+                    a.compile_jump(context, &skip, !both)?;
+                    b.compile_jump(context, label, both)?;
+                    context.add(format!("label '{}", skip));
+                } else {
+                    a.compile_jump(context, label, when)?;
+                    b.compile_jump(context, label, when)?;
+                }
             }
         }
         Ok(())
@@ -192,15 +789,213 @@ impl<'a> Instruction<'a> {
 }
 
 impl<'a> Expression<'a> {
+    /// Type the value of the expression needs: the widest of its variables and numbers, signed
+    /// if any of them is signed or negated.
+    pub fn ty(&self, context: &CompilationContext) -> Type {
+        match self {
+            Expression::Number(a, _) => Type::Unsigned(match a {
+                0..=15 => 1,
+                16..=255 => 2,
+                _ => 4,
+            }),
+            Expression::Negate(a, _) => match a.as_ref() {
+                Expression::Number(a, _) => Type::Signed(match a {
+                    0..=8 => 1,
+                    9..=128 => 2,
+                    _ => 4,
+                }),
+                a => Type::Signed(a.ty(context).width()),
+            },
+            Expression::Overflow(_, a, _) => a.ty(context),
+            Expression::Variable(a, _) => match context.type_of(a) {
+                Type::Array(_) => Type::Unsigned(1),
+                e => e,
+            },
+            Expression::Operation(_, a, b, _) => a.common(b, context),
+            Expression::FunctionCall(..) | Expression::Index(..) => Type::Unsigned(1),
+        }
+    }
+
+    /// Type both the expression and `other` are used as, a literal taking the type of the other
+    /// side when it fits in it.
+    fn common(&self, other: &Expression, context: &CompilationContext) -> Type {
+        let (a, b) = (self.ty(context), other.ty(context));
+        match (self.literal(), other.literal()) {
+            (Some(e), _) if b.min() <= e && e <= b.max() => b,
+            (_, Some(e)) if a.min() <= e && e <= a.max() => a,
+            _ => a.common(b),
+        }
+    }
+
+    /// The number the expression is if it is a literal, such as `3` or `-3`.
+    fn literal(&self) -> Option<i32> {
+        match self {
+            Expression::Number(a, _) => Some(i32::from(*a)),
+            Expression::Negate(a, _) => match a.as_ref() {
+                Expression::Number(a, _) => Some(-i32::from(*a)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Lowers the expression to a value of type `ty` and returns it, as the output of the
+    /// expression: a variable or `&n` for a `u4` or `i4`, otherwise a wide variable, narrower
+    /// values being zero-extended or sign-extended if they are signed.
+    pub fn compile_as(&self, context: &mut CompilationContext, ty: Type) -> Result<String> {
+        let out = self.lower_as(context, ty)?;
+        context.current_expression_out_expr = Cow::Owned(out.clone());
+        Ok(out)
+    }
+
+    fn lower_as(&self, context: &mut CompilationContext, ty: Type) -> Result<String> {
+        let width = ty.width();
+        if let Some(value) = self.literal() {
+            if value < ty.min() || value > ty.max() {
+                return Err(SourceError::new(
+                    self.span(),
+                    format!("`{}` doesn't fit in a `{}`", value, ty),
+                )
+                .with_note(format!(
+                    "note: `{}` values go from {} to {}",
+                    ty,
+                    ty.min(),
+                    ty.max()
+                ))
+                .into());
+            }
+            let bits = value.rem_euclid(1 << (4 * width)) as u16;
+            return Ok(if width == 1 {
+                format!("&{}", bits)
+            } else {
+                context.wide_temporary(width, bits)
+            });
+        }
+        match self {
+            Expression::Variable(a, span) => {
+                let variable = context.resolve(a, *span)?;
+                let found = context.scopes.type_of(&variable);
+                // An unsigned value only fits in a signed type wider than itself.
+                let fits = match (found.is_signed(), ty.is_signed()) {
+                    (true, false) => false,
+                    (false, true) => found.width() < width,
+                    _ => found.width() <= width,
+                };
+                if !fits {
+                    let error = mismatched(a, found, ty, *span);
+                    return Err(if found.is_signed() || !ty.is_signed() {
+                        error
+                    } else {
+                        error.with_note(format!(
+                            "note: an `{}` can't hold every `{}` value",
+                            ty, found
+                        ))
+                    }
+                    .into());
+                }
+                if found.width() == width {
+                    return Ok(variable);
+                }
+                let result = context.wide_temporary(width, 0);
+                let widen = if found.is_signed() { "swiden" } else { "widen" };
+                context.add(format!("{} {} {}", widen, result, variable));
+                Ok(result)
+            }
+            Expression::Negate(a, span) => {
+                check_overflow(context.overflow, ty, *span)?;
+                let value = a.compile_as(context, ty)?;
+                if width == 1 {
+                    let result = context.count();
+                    context.add(format!("let TMP{} 0", result));
+                    context.add(format!("sub TMP{} &0 {}", result, value));
+                    Ok(format!("TMP{}", result))
+                } else {
+                    let zero = context.wide_temporary(width, 0);
+                    let result = context.wide_temporary(width, 0);
+                    context.add(format!("wsub {} {} {}", result, zero, value));
+                    Ok(result)
+                }
+            }
+            Expression::Operation(operator @ (Operator::Div | Operator::Mod), _, _, span)
+                if ty.is_signed() =>
+            {
+                Err(SourceError::new(
+                    *span,
+                    format!("`{}` can't be used on `{}` values", operator.symbol(), ty),
+                )
+                .with_note("note: signed numbers only support `+`, `-`, `*` and comparisons")
+                .into())
+            }
+            Expression::Overflow(mode, a, span) => {
+                check_overflow(*mode, ty, *span)?;
+                let outer = std::mem::replace(&mut context.overflow, *mode);
+                let value = a.compile_as(context, ty);
+                context.overflow = outer;
+                value
+            }
+            Expression::Operation(operator, a, b, span) if width == 1 => {
+                check_overflow(context.overflow, ty, *span)?;
+                let result = context.count();
+                let left = a.compile_as(context, ty)?;
+                let right = b.compile_as(context, ty)?;
+                let instruction = match ty {
+                    Type::Unsigned(_) => context.overflow.instruction(*operator),
+                    _ => operator.instruction().to_owned(),
+                };
+                context.add(format!("let TMP{} 0", result));
+                context.add(format!("{} TMP{} {} {}", instruction, result, left, right));
+                Ok(format!("TMP{}", result))
+            }
+            Expression::Operation(operator @ (Operator::Add | Operator::Sub), a, b, span) => {
+                check_overflow(context.overflow, ty, *span)?;
+                let left = a.compile_as(context, ty)?;
+                let right = b.compile_as(context, ty)?;
+                let result = context.wide_temporary(width, 0);
+                context.add(format!(
+                    "w{} {} {} {}",
+                    operator.instruction(),
+                    result,
+                    left,
+                    right
+                ));
+                Ok(result)
+            }
+            Expression::Operation(operator, _, _, span) => Err(SourceError::new(
+                *span,
+                format!("`{}` can't be used on `{}` values", operator.symbol(), ty),
+            )
+            .with_note("note: numbers wider than `u4` only support `+`, `-` and comparisons")
+            .into()),
+            _ => {
+                self.compile(context)?;
+                if width == 1 {
+                    return Ok(context.current_expression_out_expr.to_string());
+                }
+                let result = context.wide_temporary(width, 0);
+                context.add(format!(
+                    "widen {} {}",
+                    result, context.current_expression_out_expr
+                ));
+                Ok(result)
+            }
+        }
+    }
+
     pub fn compile(&self, context: &mut CompilationContext) -> Result<()> {
         match self {
-            Expression::FunctionCall(a, b) => {
+            Expression::FunctionCall(a, b, span) => {
                 let calln = context.count();
-                context.check_func(a.as_ref(), b)?;
-                if context.functions_refs.get(a.as_ref()).unwrap().0 {
+                let a = &context.function(a);
+                context.check_func(a, b, *span)?;
+                if context.functions_refs.get(a).unwrap().0 {
+                    // `exit` of a signed number shows it as one.
+                    let instruction = match b.as_slice() {
+                        [e] if a == "exit" && e.ty(context).is_signed() => "exit_signed",
+                        _ => a,
+                    };
                     let s = format!(
                         "{} {}",
-                        a,
+                        instruction,
                         b.iter()
                             .map(|x| {
                                 x.compile(context)?;
@@ -214,7 +1009,7 @@ impl<'a> Expression<'a> {
                 } else {
                     let s = format!(
                         "call {} {}",
-                        a,
+                        ir_name(a),
                         b.iter()
                             .map(|x| {
                                 x.compile(context)?;
@@ -226,31 +1021,74 @@ impl<'a> Expression<'a> {
                     );
                     context.add(s);
                     context.add(format!("let TMP{} 0", calln));
-                    context.add(format!("set TMP{} {}_out", calln, a));
+                    context.add(format!("set TMP{} {}_out", calln, ir_name(a)));
                     context.current_expression_out_expr = Cow::Owned(format!("TMP{}", calln))
                 }
             }
-            Expression::Variable(a) => {
-                if context
-                    .current_function_context
-                    .as_ref()
-                    .map(|x| x.arguments.contains(a))
-                    .unwrap_or(false)
-                {
-                    context.current_expression_out_expr = Cow::Owned(format!("${}", a));
-                } else {
-                    if !context.existing_vars.contains(a) {
-                        context
-                            .existing_vars
-                            .insert(Cow::Owned(a.as_ref().to_owned()));
-                        context.add(format!("let {} 0", a))
-                    }
-                    context.current_expression_out_expr = Cow::Owned(a.as_ref().to_owned());
+            Expression::Variable(a, span) => {
+                let variable = context.resolve(a, *span)?;
+                let ty = context.scopes.type_of(&variable);
+                if ty.width() != 1 {
+                    return Err(mismatched(a, ty, Type::Unsigned(1), *span)
+                        .with_note(
+                            "note: function arguments, return values and array elements are \
+                             `u4` values",
+                        )
+                        .into());
                 }
+                context.current_expression_out_expr = Cow::Owned(variable);
             }
-            Expression::Number(a) => {
+            Expression::Number(a, span) => {
+                if *a > 15 {
+                    return Err(
+                        SourceError::new(*span, format!("`{}` doesn't fit in a `u4`", a))
+                            .with_note("note: `u4` values go from 0 to 15")
+                            .with_note(
+                                "help: declare a `u8` or `u16` variable with `let` to hold it",
+                            )
+                            .into(),
+                    );
+                }
                 context.current_expression_out_expr = Cow::Owned(format!("&{}", a));
             }
+            Expression::Negate(..) => {
+                self.compile_as(context, Type::Signed(1))?;
+            }
+            Expression::Index(a, b, span) => {
+                let array = context.array(a, b, *span)?;
+                let element = context.count();
+                b.compile(context)?;
+                context.add(format!("let TMP{} 0", element));
+                context.add(format!(
+                    "load TMP{} {} {}",
+                    element, array, context.current_expression_out_expr
+                ));
+                context.current_expression_out_expr = Cow::Owned(format!("TMP{}", element))
+            }
+            Expression::Overflow(mode, a, span) => {
+                check_overflow(*mode, self.ty(context), *span)?;
+                let outer = std::mem::replace(&mut context.overflow, *mode);
+                let value = a.compile(context);
+                context.overflow = outer;
+                value?;
+            }
+            Expression::Operation(operator, a, b, span) => {
+                check_overflow(context.overflow, self.ty(context), *span)?;
+                let instruction = match self.ty(context) {
+                    Type::Unsigned(_) => context.overflow.instruction(*operator),
+                    _ => operator.instruction().to_owned(),
+                };
+                let result = context.count();
+                a.compile(context)?;
+                let left = context.current_expression_out_expr.clone();
+                b.compile(context)?;
+                context.add(format!("let TMP{} 0", result));
+                context.add(format!(
+                    "{} TMP{} {} {}",
+                    instruction, result, left, context.current_expression_out_expr
+                ));
+                context.current_expression_out_expr = Cow::Owned(format!("TMP{}", result))
+            }
         }
         Ok(())
     }
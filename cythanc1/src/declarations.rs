@@ -0,0 +1,341 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+};
+
+use anyhow::Result;
+
+use crate::{
+    ir_name, module_of, qualify, BooleanExpression, CodeBlock, CompilationContext, Expression,
+    FileElement, Instruction, Operator, Overflow, SourceError,
+};
+
+/// Declares the signature of every function of the file, so that a body can call functions
+/// defined after it, finds the recursive ones and evaluates the constants.
+pub fn declare(elements: &[FileElement], context: &mut CompilationContext) -> Result<()> {
+    let mut defined: HashMap<&str, &FileElement> = HashMap::new();
+    for element in elements {
+        let (name, span) = match element {
+            FileElement::Function(a, _, _, e)
+            | FileElement::FunctionExtern(a, _, e)
+            | FileElement::Constant(a, _, e)
+            | FileElement::Static(a, _, e) => (a, e),
+            FileElement::Import(..) => continue,
+        };
+        if let FileElement::Function(..) | FileElement::FunctionExtern(..) = element {
+            let base = name.rsplit("::").next().unwrap_or(name);
+            let modes = [Overflow::Wrapping, Overflow::Checked, Overflow::Saturating];
+            if modes.iter().any(|x| x.to_string() == base) {
+                return Err(SourceError::new(
+                    *span,
+                    format!("`{}` is an overflow mode and can't name a function", base),
+                )
+                .with_note(format!(
+                    "note: `{}(...)` always computes its argument with {} arithmetic",
+                    base, base
+                ))
+                .into());
+            }
+        }
+        let extern_again = |x: &&FileElement| match (x, element) {
+            (FileElement::FunctionExtern(_, a, _), FileElement::FunctionExtern(_, b, _)) => {
+                a.len() == b.len()
+            }
+            _ => false,
+        };
+        if let Some(first) = defined.insert(name, element).filter(|x| !extern_again(x)) {
+            return Err(
+                SourceError::new(*span, format!("`{}` is defined twice", name))
+                    .with_note(format!(
+                        "note: it is first defined as `{}`",
+                        signature(first)
+                    ))
+                    .into(),
+            );
+        }
+        match element {
+            FileElement::Function(a, b, ..) | FileElement::FunctionExtern(a, b, _)
+                if a != "main" =>
+            {
+                context.functions_refs.insert(
+                    a.to_string(),
+                    (
+                        matches!(element, FileElement::FunctionExtern(..)),
+                        b.iter().map(|x| Cow::Owned(x.to_string())).collect(),
+                    ),
+                );
+            }
+            FileElement::Static(a, ..) => {
+                context
+                    .statics
+                    .insert(a.to_string(), ir_name(&format!("static.{}", a)));
+            }
+            _ => (),
+        }
+    }
+    let constants = elements
+        .iter()
+        .filter_map(|x| match x {
+            FileElement::Constant(a, b, _) => Some((a.as_ref(), b)),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+    for element in elements {
+        if let FileElement::Constant(a, ..) = element {
+            evaluate(a, &constants, &mut Vec::new(), &mut context.constants)?;
+        }
+    }
+    let graph = call_graph(elements);
+    context.recursive = graph
+        .keys()
+        .filter(|x| reaches(&graph, x, x))
+        .map(|x| x.to_string())
+        .collect();
+    Ok(())
+}
+
+/// Evaluates the constant `name` and the constants it uses into `values`, `evaluating` being the
+/// constants whose value is being computed.
+fn evaluate<'a>(
+    name: &'a str,
+    constants: &HashMap<&'a str, &'a Expression>,
+    evaluating: &mut Vec<&'a str>,
+    values: &mut HashMap<String, u8>,
+) -> Result<u8> {
+    if let Some(e) = values.get(name) {
+        return Ok(*e);
+    }
+    if evaluating.contains(&name) {
+        return Err(SourceError::new(
+            constants[name].span(),
+            format!("constant `{}` depends on itself", name),
+        )
+        .with_note(format!("note: {} -> {}", evaluating.join(" -> "), name))
+        .into());
+    }
+    evaluating.push(name);
+    let value = fold(
+        constants[name],
+        constants,
+        evaluating,
+        values,
+        Overflow::Wrapping,
+    )?;
+    evaluating.pop();
+    values.insert(name.to_owned(), value);
+    Ok(value)
+}
+
+/// Value of the constant expression `expression`, its arithmetic overflowing as `overflow` says.
+fn fold<'a>(
+    expression: &'a Expression,
+    constants: &HashMap<&'a str, &'a Expression>,
+    evaluating: &mut Vec<&'a str>,
+    values: &mut HashMap<String, u8>,
+    overflow: Overflow,
+) -> Result<u8> {
+    match expression {
+        Expression::Number(a, span) => {
+            u8::try_from(*a).ok().filter(|x| *x <= 15).ok_or_else(|| {
+                SourceError::new(*span, "number out of range")
+                    .with_note("note: constants are `u4` values, from 0 to 15")
+                    .into()
+            })
+        }
+        Expression::Variable(a, span) => {
+            let module = evaluating.last().map(|x| module_of(x)).unwrap_or("");
+            let name = qualify(module, a, |x| constants.contains_key(x));
+            if let Some((name, _)) = constants.get_key_value(name.as_str()) {
+                evaluate(name, constants, evaluating, values)
+            } else {
+                Err(
+                    SourceError::new(*span, format!("`{}` is not a constant", a))
+                        .with_note("note: constants can only use numbers and other constants")
+                        .into(),
+                )
+            }
+        }
+        Expression::Operation(operator, a, b, span) => {
+            let a = fold(a, constants, evaluating, values, overflow)?;
+            let b = fold(b, constants, evaluating, values, overflow)?;
+            let exact = match operator {
+                Operator::Add => i32::from(a) + i32::from(b),
+                Operator::Sub => i32::from(a) - i32::from(b),
+                Operator::Mul => i32::from(a) * i32::from(b),
+                Operator::Div | Operator::Mod => return Ok(operator.apply(a, b)),
+            };
+            match overflow {
+                Overflow::Checked if !(0..=15).contains(&exact) => Err(SourceError::new(
+                    *span,
+                    format!("`{} {} {}` overflows", a, operator.symbol(), b),
+                )
+                .with_note("note: constants are `u4` values, from 0 to 15")
+                .into()),
+                Overflow::Saturating => Ok(exact.clamp(0, 15) as u8),
+                _ => Ok(operator.apply(a, b)),
+            }
+        }
+        Expression::Negate(a, _) => {
+            Ok((16 - fold(a, constants, evaluating, values, overflow)?) % 16)
+        }
+        Expression::Overflow(mode, a, _) => fold(a, constants, evaluating, values, *mode),
+        Expression::FunctionCall(a, _, span) => Err(SourceError::new(
+            *span,
+            format!("constants can't call functions such as `{}`", a),
+        )
+        .into()),
+        Expression::Index(a, _, span) => Err(SourceError::new(
+            *span,
+            format!("constants can't read arrays such as `{}`", a),
+        )
+        .into()),
+    }
+}
+
+/// `fn name(arguments)` as written in the source.
+fn signature(element: &FileElement) -> String {
+    match element {
+        FileElement::Function(a, b, _, _) => format!("fn {}({})", a, b.join(", ")),
+        FileElement::FunctionExtern(a, b, _) => format!("extern fn {}({})", a, b.join(", ")),
+        FileElement::Constant(a, ..) => format!("const {}", a),
+        FileElement::Static(a, b, _) => format!("static {} = {}", a, b),
+        FileElement::Import(a, _) => format!("import \"{}\"", a),
+    }
+}
+
+/// Functions every function of the program calls.
+fn call_graph<'a>(elements: &'a [FileElement]) -> HashMap<&'a str, Vec<&'a str>> {
+    let functions = elements
+        .iter()
+        .filter_map(|x| match x {
+            FileElement::Function(a, ..) => Some(a.as_ref()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    elements
+        .iter()
+        .filter_map(|x| match x {
+            FileElement::Function(a, _, c, _) => {
+                let mut calls = Vec::new();
+                c.calls(&mut calls);
+                let calls = calls
+                    .into_iter()
+                    .filter_map(|x| {
+                        let name = qualify(module_of(a), x, |x| functions.contains(x));
+                        functions.get(name.as_str()).copied()
+                    })
+                    .collect();
+                Some((a.as_ref(), calls))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether a chain of calls leads from `from` to `to`.
+fn reaches(graph: &HashMap<&str, Vec<&str>>, from: &str, to: &str) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = vec![from];
+    while let Some(name) = stack.pop() {
+        for callee in graph.get(name).into_iter().flatten() {
+            if *callee == to {
+                return true;
+            }
+            if seen.insert(*callee) {
+                stack.push(callee);
+            }
+        }
+    }
+    false
+}
+
+impl<'a> CodeBlock<'a> {
+    fn calls<'b>(&'b self, calls: &mut Vec<&'b str>) {
+        for i in &self.code {
+            i.calls(calls);
+        }
+    }
+}
+
+impl<'a> Instruction<'a> {
+    fn calls<'b>(&'b self, calls: &mut Vec<&'b str>) {
+        match self {
+            Instruction::Expression(a) => a.calls(calls),
+            Instruction::Assign(_, a, _) => a.calls(calls),
+            Instruction::Store(_, a, b, _) => {
+                a.calls(calls);
+                b.calls(calls);
+            }
+            Instruction::Return(a, _) => {
+                if let Some(a) = a {
+                    a.calls(calls);
+                }
+            }
+            Instruction::If(a, b, c, _) => {
+                a.calls(calls);
+                b.calls(calls);
+                if let Some(c) = c {
+                    c.calls(calls);
+                }
+            }
+            Instruction::Loop(a, _) => a.calls(calls),
+            Instruction::While(a, b, _) => {
+                a.calls(calls);
+                b.calls(calls);
+            }
+            Instruction::For(_, a, b, c, _) => {
+                a.calls(calls);
+                b.calls(calls);
+                c.calls(calls);
+            }
+            Instruction::Repeat(a, b, _) => {
+                a.calls(calls);
+                b.calls(calls);
+            }
+            Instruction::Let(_, _, a, _) => {
+                if let Some(a) = a {
+                    a.calls(calls);
+                }
+            }
+            Instruction::Continue(_) | Instruction::Break(_) => {}
+        }
+    }
+}
+
+impl<'a> BooleanExpression<'a> {
+    fn calls<'b>(&'b self, calls: &mut Vec<&'b str>) {
+        match self {
+            BooleanExpression::Test(a, _, b) => {
+                a.calls(calls);
+                b.calls(calls);
+            }
+            BooleanExpression::Not(a) => a.calls(calls),
+            BooleanExpression::And(a, b) | BooleanExpression::Or(a, b) => {
+                a.calls(calls);
+                b.calls(calls);
+            }
+        }
+    }
+}
+
+impl<'a> Expression<'a> {
+    fn calls<'b>(&'b self, calls: &mut Vec<&'b str>) {
+        match self {
+            Expression::FunctionCall(a, b, _) => {
+                calls.push(a);
+                for i in b {
+                    i.calls(calls);
+                }
+            }
+            Expression::Operation(_, a, b, _) => {
+                a.calls(calls);
+                b.calls(calls);
+            }
+            Expression::Index(_, a, _)
+            | Expression::Negate(a, _)
+            | Expression::Overflow(_, a, _) => a.calls(calls),
+            Expression::Variable(..) | Expression::Number(..) => {}
+        }
+    }
+}
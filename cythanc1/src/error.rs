@@ -0,0 +1,201 @@
+use std::fmt::Display;
+
+use cythanc::Diagnostic;
+
+use pest::error::{ErrorVariant, InputLocation};
+
+use crate::{Rule, Sources, Span};
+
+/// Error pointing at a place of the source file being compiled.
+#[derive(Debug, Clone)]
+pub struct SourceError {
+    pub span: Span,
+    pub message: String,
+    /// Extra `help: ...` or `note: ...` lines.
+    pub notes: Vec<String>,
+}
+
+impl SourceError {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Converts the error to a diagnostic rendering the line of `source` it points to.
+    pub fn to_diagnostic(&self, file: &str, source: &str) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(self.message.clone()).in_source(
+            file,
+            source,
+            self.span.start,
+            self.span.end,
+        );
+        diagnostic.notes = self.notes.clone();
+        diagnostic
+    }
+}
+
+impl Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+/// Renders `error`, with a snippet of the file of `sources` it points into.
+pub fn render_error(error: &anyhow::Error, sources: &Sources) -> String {
+    match error.downcast_ref::<SourceError>() {
+        Some(e) => {
+            let file = sources.file(e.span);
+            e.to_diagnostic(&file.name, &file.source).to_string()
+        }
+        None => format!("error: {}", error),
+    }
+}
+
+/// Turns a pest error into an error listing the expected tokens in user terms.
+pub(crate) fn syntax_error(error: &pest::error::Error<Rule>, source: &str) -> SourceError {
+    let (position, mut expected) = match error.parse_attempts() {
+        Some(attempts) => (
+            attempts.max_position,
+            describe_tokens(attempts.expected_tokens().iter().map(|x| x.to_string())),
+        ),
+        None => (
+            match error.location {
+                InputLocation::Pos(e) | InputLocation::Span((e, _)) => e,
+            },
+            Vec::new(),
+        ),
+    };
+    if expected.is_empty() {
+        if let ErrorVariant::ParsingError { positives, .. } = &error.variant {
+            expected = positives.iter().map(|x| describe_rule(*x)).collect();
+        }
+    }
+    expected.sort();
+    expected.dedup();
+    let (length, found) = found_token(source, position);
+    let message = match expected.split_last() {
+        None => format!("unexpected {}", found),
+        Some((last, [])) if last == "`;`" => {
+            format!(
+                "expected `;` after {}, found {}",
+                previous_token(source, position),
+                found
+            )
+        }
+        Some((last, [])) => format!("expected {}, found {}", last, found),
+        Some((last, rest)) => format!("expected {} or {}, found {}", rest.join(", "), last, found),
+    };
+    SourceError::new(
+        Span {
+            file: 0,
+            start: position,
+            end: position + length,
+        },
+        message,
+    )
+}
+
+/// Describes tokens from their display form, pest not exporting the token type itself.
+///
+/// Operators that could continue an expression are only mentioned when nothing else fits, as
+/// they are rarely what is missing.
+fn describe_tokens(tokens: impl Iterator<Item = String>) -> Vec<String> {
+    let (mut name, mut number, mut parenthesis, mut operator) = (false, false, false, false);
+    let mut comparison = false;
+    let mut out = Vec::new();
+    for token in tokens {
+        let chars: Vec<char> = token.chars().collect();
+        match chars.as_slice() {
+            ['0', '.', '.', _] => number = true,
+            [_, '.', '.', _] | ['_'] => name = true,
+            ['('] => parenthesis = true,
+            ['+' | '-' | '*' | '/' | '%'] => operator = true,
+            ['<' | '>'] | ['<' | '>' | '=' | '!', '='] => comparison = true,
+            _ if token.trim().is_empty() || token == "BUILTIN_RULE" => (),
+            _ => out.push(format!("`{}`", token.trim())),
+        }
+    }
+    match (name, number) {
+        (true, true) => out.push("an expression".to_owned()),
+        (true, false) => out.push("a name".to_owned()),
+        (false, true) => out.push("a number".to_owned()),
+        (false, false) => (),
+    }
+    if comparison {
+        out.push("a comparison operator".to_owned());
+    }
+    if parenthesis && !(name && number) {
+        out.push("`(`".to_owned());
+    }
+    if operator && out.is_empty() {
+        out.push("an operator".to_owned());
+    }
+    out
+}
+
+fn describe_rule(rule: Rule) -> String {
+    match rule {
+        Rule::expr
+        | Rule::term
+        | Rule::factor
+        | Rule::func_call
+        | Rule::index
+        | Rule::negation
+        | Rule::overflow => "an expression",
+        Rule::overflow_mode => "`checked`, `saturating` or `wrapping`",
+        Rule::integer_type | Rule::array_type => "a type",
+        Rule::add_op | Rule::mul_op => "an operator",
+        Rule::literal | Rule::path | Rule::alpha => "a name",
+        Rule::string => "a string",
+        Rule::number | Rule::numeric => "a number",
+        Rule::test => "a comparison operator",
+        Rule::boolean_expr | Rule::boolean_and | Rule::boolean_atom | Rule::comparison => {
+            "a condition"
+        }
+        Rule::code_block => "a `{` block",
+        Rule::instruction => "a statement",
+        Rule::function | Rule::extern_function => "`fn` or `extern fn`",
+        Rule::constant => "`const`",
+        Rule::static_variable => "`static`",
+        Rule::import => "`import`",
+        Rule::EOI => "end of file",
+        _ => return format!("{:?}", rule),
+    }
+    .to_owned()
+}
+
+/// What the code before `position` ends with, to say what a missing `;` should follow.
+fn previous_token(source: &str, position: usize) -> String {
+    let before = source[..position.min(source.len())].trim_end();
+    let word = &before[before
+        .rfind(|x: char| !x.is_alphanumeric() && x != '_')
+        .map(|x| x + 1)
+        .unwrap_or(0)..];
+    match word {
+        "break" | "continue" | "return" => format!("`{}`", word),
+        _ => "expression".to_owned(),
+    }
+}
+
+/// Length and description of the token starting at `position`.
+fn found_token(source: &str, position: usize) -> (usize, String) {
+    let rest = &source[position.min(source.len())..];
+    let length = match rest.chars().next() {
+        None => return (0, "end of file".to_owned()),
+        Some(e) if e.is_alphanumeric() || e == '_' => rest
+            .find(|x: char| !x.is_alphanumeric() && x != '_')
+            .unwrap_or(rest.len()),
+        Some(e) => e.len_utf8(),
+    };
+    (length, format!("`{}`", &rest[..length]))
+}
@@ -0,0 +1,67 @@
+//! Compiler from the high-level Cythan language to the IR understood by `cythanc`.
+
+extern crate pest;
+#[macro_use]
+extern crate pest_derive;
+
+use std::borrow::Cow;
+
+use anyhow::{anyhow, Result};
+use pest::Parser;
+
+mod parser;
+use parser::*;
+
+mod struct_defs;
+pub use struct_defs::*;
+
+mod compiler;
+pub use compiler::*;
+
+mod declarations;
+pub use declarations::*;
+
+mod modules;
+pub use modules::*;
+
+mod error;
+pub use error::*;
+
+mod pipeline;
+pub use pipeline::*;
+
+#[derive(Parser)]
+#[grammar = "../gramar.pest"]
+pub struct CtParser;
+
+/// Parses a source file into its top-level elements, see [`Sources::parse`] for programs made of
+/// several files.
+pub fn parse(source: &str) -> Result<Vec<FileElement<'_>>> {
+    pest::set_error_detail(true);
+    let file = CtParser::parse(Rule::file, source)
+        .map_err(|e| syntax_error(&e, source))?
+        .next()
+        .unwrap();
+    let elements: Vec<Option<FileElement>> = file.parse()?;
+    Ok(elements.into_iter().flatten().collect())
+}
+
+/// Lowers parsed elements to IR lines, one instruction per line, along with the span of the
+/// statement every line comes from.
+///
+/// Functions are declared before any body is lowered, so they can be defined in any order.
+/// `overflow` is what `u4` arithmetic does on overflow outside of `checked(...)` and the like.
+pub fn lower(elements: &[FileElement], overflow: Overflow) -> Result<(Vec<String>, Vec<Span>)> {
+    let mut context = CompilationContext {
+        overflow,
+        ..Default::default()
+    };
+    declare(elements, &mut context)?;
+    for element in elements {
+        element.compile(&mut context)?;
+    }
+    Ok((
+        context.asm_file.into_iter().map(Cow::into_owned).collect(),
+        context.origins,
+    ))
+}
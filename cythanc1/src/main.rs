@@ -1,38 +1,187 @@
-extern crate pest;
-#[macro_use]
-extern crate pest_derive;
+use std::process::exit;
 
-use std::{borrow::Cow, collections::HashMap};
+use cythanc::{
+    assembler::assemble,
+    cli::{run_output, Emit, Options, EXIT_CODES},
+    debugger::{repl, Debugger},
+    io::{display_path, read_input, write_output, EXIT_COMPILE_ERROR, EXIT_IO, EXIT_USAGE},
+    vm, DEFAULT_TEMPLATE,
+};
+use cythanc1::{compile_program, render_error, Overflow, Sources};
 
-use anyhow::{anyhow, Result};
+const USAGE: &str = "Usage: cythanc1 [OPTIONS] [INPUT]
 
-use pest::Parser;
+Compiles a high-level Cythan source file to Cythan template code.
 
-mod parser;
-use parser::*;
+Arguments:
+  [INPUT]                 Source file (`-` reads stdin, the default), the files
+                          it imports being read relative to it
 
-mod struct_defs;
-pub use struct_defs::*;
+Options:
+  -o, --output <FILE>     Write the result to FILE (`-` for stdout, the default)
+  -t, --template <FILE>   Expand FILE instead of the built-in template.ct
+      --emit <KIND>       What to write: `template` (default), `sections`, `ir`,
+                          `image` (the assembled memory) or `listing` (annotated
+                          memory)
+      --run               Run the program and write its exit value instead
+      --debug             Run the program in a step debugger reading commands from stdin
+      --max-cycles <N>    Stop a run after N cycles (default 10000000)
+      --overflow <MODE>   What `u4` arithmetic does on overflow: `wrapping` (default),
+                          `checked` (stop the program, reporting the operation) or
+                          `saturating` (stay at 0 or 15)
+      --dump-ast <FILE>   Also write the parsed source to FILE
+      --dump-ir <FILE>    Also write the intermediate IR to FILE
+      --source-map <FILE> Also write the source line every template line comes from to FILE
+      --annotate          Mark generated template code with `# from file:line` comments
+  -h, --help              Print this message";
 
-mod compiler;
-use compiler::*;
+const EMITS: [Emit; 5] = [
+    Emit::Template,
+    Emit::Sections,
+    Emit::Ir,
+    Emit::Image,
+    Emit::Listing,
+];
 
-#[derive(Parser)]
-#[grammar = "../gramar.pest"]
-pub struct CtParser;
+/// Options of `cythanc1` only.
+#[derive(Default)]
+struct Extra {
+    overflow: Overflow,
+    dump_ast: Option<String>,
+    dump_ir: Option<String>,
+}
+
+fn parse_options(args: impl Iterator<Item = String>) -> Result<(Options, Extra), String> {
+    let mut extra = Extra::default();
+    let options = Options::parse(args, &EMITS, |arg, value| {
+        match arg {
+            "--dump-ast" => extra.dump_ast = Some(value()?),
+            "--dump-ir" => extra.dump_ir = Some(value()?),
+            "--overflow" => {
+                let value = value()?;
+                extra.overflow = match value.as_str() {
+                    "wrapping" => Overflow::Wrapping,
+                    "checked" => Overflow::Checked,
+                    "saturating" => Overflow::Saturating,
+                    _ => return Err(format!("unknown overflow mode `{}`", value)),
+                };
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    if options.inputs.len() > 1 {
+        return Err(
+            "only one source file can be given, the files it imports being read relative to it"
+                .to_owned(),
+        );
+    }
+    Ok((options, extra))
+}
 
 fn main() {
-    let unparsed_file = std::fs::read_to_string("in.ct").expect("cannot read file");
-
-    let file = CtParser::parse(Rule::file, &unparsed_file)
-        .expect("unsuccessful parse") // unwrap the parse result
-        .next()
-        .unwrap();
-    let functions: Vec<Option<FileElement>> = file.parse().unwrap();
-    let functions: Vec<FileElement> = functions.into_iter().flatten().collect();
-    let mut context = CompilationContext::default();
-    functions
-        .iter()
-        .for_each(|x| x.compile(&mut context).unwrap());
-    std::fs::write("out.ct", context.asm_file.join("\n")).unwrap();
+    let (options, extra) = match parse_options(std::env::args().skip(1)) {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}\n\n{}", e, USAGE, EXIT_CODES);
+            exit(EXIT_USAGE);
+        }
+    };
+    if options.help {
+        println!("{}\n\n{}", USAGE, EXIT_CODES);
+        return;
+    }
+    let input = &options.inputs[0];
+    let template = match &options.template {
+        Some(path) => read_input(path).unwrap_or_else(|e| fail(EXIT_IO, &e)),
+        None => DEFAULT_TEMPLATE.replace('\r', ""),
+    };
+    let source = read_input(input).unwrap_or_else(|e| fail(EXIT_IO, &e));
+    let mut sources = Sources::new(display_path(input), input, &source);
+    let compilation =
+        compile_program(&mut sources, &template, extra.overflow).unwrap_or_else(|e| {
+            eprintln!("{}", render_error(&e, &sources));
+            exit(EXIT_COMPILE_ERROR)
+        });
+    if let Some(path) = &extra.dump_ast {
+        write_output(path, &compilation.ast).unwrap_or_else(|e| fail(EXIT_IO, &e));
+    }
+    if let Some(path) = &extra.dump_ir {
+        write_output(path, &compilation.ir.join("\n")).unwrap_or_else(|e| fail(EXIT_IO, &e));
+    }
+    let source_map = compilation.source_map(&sources);
+    if let Some(path) = &options.source_map {
+        write_output(path, &source_map.to_string()).unwrap_or_else(|e| fail(EXIT_IO, &e));
+    }
+    let assembled = || {
+        assemble("<template output>", &compilation.output).unwrap_or_else(|e| {
+            eprintln!("{}\n", e.locate(&[&compilation.ir_source_map, &source_map]));
+            fail(EXIT_COMPILE_ERROR, "could not assemble the template output")
+        })
+    };
+    if options.debug {
+        let image = assembled();
+        let maps = vec![compilation.ir_source_map.clone(), source_map];
+        let mut debugger = Debugger::new(image, maps, options.max_cycles);
+        debugger.add_source("<ir>", &compilation.ir.join("\n"));
+        for file in &sources.files {
+            debugger.add_source(&file.name, &file.source);
+        }
+        let stdin = std::io::stdin();
+        repl(
+            &mut debugger,
+            display_path(input),
+            stdin.lock(),
+            std::io::stdout(),
+        )
+        .unwrap_or_else(|e| fail(EXIT_IO, &e.to_string()));
+        return;
+    }
+    let output = if options.run {
+        run_output(vm::run(&assembled(), options.max_cycles), &source_map)
+            .unwrap_or_else(|(code, message)| fail(code, &message))
+    } else {
+        match options.emit {
+            Emit::Ir => compilation.ir.join("\n"),
+            Emit::Image => assembled().to_numbers(),
+            Emit::Listing => assembled().listing(&compilation.output),
+            Emit::Template if options.annotate => source_map.annotate(&compilation.output),
+            Emit::Template => compilation.output.clone(),
+            Emit::Sections => compilation.sections.clone(),
+        }
+    };
+    write_output(&options.output, &output).unwrap_or_else(|e| fail(EXIT_IO, &e));
+}
+
+fn fail(code: i32, message: &str) -> ! {
+    eprintln!("error: {}", message);
+    exit(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<(Options, Extra), String> {
+        parse_options(args.iter().map(|x| x.to_string()))
+    }
+
+    #[test]
+    fn parses_driver_options() {
+        let (options, extra) = parse(&["--overflow", "checked", "--dump-ir", "ir.ct", "in.ct"])
+            .unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(options.inputs, ["in.ct"]);
+        assert_eq!(extra.overflow, Overflow::Checked);
+        assert_eq!(extra.dump_ir.as_deref(), Some("ir.ct"));
+        assert_eq!(
+            parse(&["--overflow", "clamp"]).err().unwrap(),
+            "unknown overflow mode `clamp`"
+        );
+    }
+
+    #[test]
+    fn takes_one_source_file() {
+        assert!(parse(&["a.ct", "b.ct"]).is_err());
+        assert_eq!(parse(&["--emit", "ir"]).unwrap().0.emit, Emit::Ir);
+    }
 }
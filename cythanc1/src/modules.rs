@@ -0,0 +1,324 @@
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{
+    parse, BooleanExpression, CodeBlock, Expression, FileElement, Instruction, SourceError, Span,
+};
+
+/// A file of the program.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    /// Name of the file in diagnostics.
+    pub name: String,
+    /// Path the imports of the file are relative to.
+    pub path: PathBuf,
+    /// Prefix of the items of the file, empty for the compiled file.
+    pub module: String,
+    pub source: String,
+}
+
+/// Files of a program: the compiled file, then every file it imports.
+#[derive(Debug, Clone)]
+pub struct Sources {
+    pub files: Vec<SourceFile>,
+}
+
+impl Sources {
+    /// Sources of the program compiling `source`, the content of `path` shown as `name`.
+    pub fn new(name: &str, path: impl Into<PathBuf>, source: &str) -> Self {
+        Self {
+            files: vec![SourceFile {
+                name: name.to_owned(),
+                path: path.into(),
+                module: String::new(),
+                source: source.replace('\r', ""),
+            }],
+        }
+    }
+
+    /// The file `span` points into.
+    pub fn file(&self, span: Span) -> &SourceFile {
+        &self.files[span.file]
+    }
+
+    /// Reads every file the program imports, directly or through other imports.
+    pub fn load_imports(&mut self) -> Result<()> {
+        self.load(0, &mut vec![0])
+    }
+
+    /// Reads the imports of `file`, `importing` being the files whose imports are being read.
+    fn load(&mut self, file: usize, importing: &mut Vec<usize>) -> Result<()> {
+        let imports = parse(&self.files[file].source)
+            .map_err(|e| in_file(e, file))?
+            .into_iter()
+            .filter_map(|x| match x {
+                FileElement::Import(a, span) => Some((a.into_owned(), Span { file, ..span })),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for (import, span) in imports {
+            let importer = &self.files[file];
+            let path = importer
+                .path
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(&import);
+            let name = Path::new(&importer.name)
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(&import)
+                .display()
+                .to_string();
+            if let Some(index) = self.files.iter().position(|x| same_file(&x.path, &path)) {
+                if let Some(start) = importing.iter().position(|x| *x == index) {
+                    let cycle = importing[start..]
+                        .iter()
+                        .map(|x| self.files[*x].name.as_str())
+                        .chain([self.files[index].name.as_str()])
+                        .collect::<Vec<_>>();
+                    return Err(SourceError::new(span, "import cycle")
+                        .with_note(format!("note: {}", cycle.join(" imports ")))
+                        .into());
+                }
+                continue;
+            }
+            let module = path
+                .file_stem()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if !is_name(&module) {
+                return Err(
+                    SourceError::new(span, format!("`{}` can't name a module", module))
+                        .with_note(
+                            "note: the name of an imported file is used as the prefix of its items",
+                        )
+                        .into(),
+                );
+            }
+            if let Some(other) = self.files.iter().find(|x| x.module == module) {
+                return Err(SourceError::new(
+                    span,
+                    format!("a module named `{}` is already imported", module),
+                )
+                .with_note(format!("note: it is imported from `{}`", other.name))
+                .into());
+            }
+            let source = fs::read_to_string(&path)
+                .map_err(|e| SourceError::new(span, format!("cannot read `{}`: {}", name, e)))?;
+            self.files.push(SourceFile {
+                name,
+                path,
+                module,
+                source: source.replace('\r', ""),
+            });
+            importing.push(self.files.len() - 1);
+            self.load(self.files.len() - 1, importing)?;
+            importing.pop();
+        }
+        Ok(())
+    }
+
+    /// Parses every file into the elements of one program, the items of imported files being
+    /// prefixed with their module.
+    pub fn parse(&self) -> Result<Vec<FileElement<'_>>> {
+        let mut program = Vec::new();
+        for (i, file) in self.files.iter().enumerate() {
+            for mut element in parse(&file.source).map_err(|e| in_file(e, i))? {
+                element.relocate(i);
+                if !file.module.is_empty() {
+                    element.prefix(&file.module);
+                }
+                if !matches!(element, FileElement::Import(..)) {
+                    program.push(element);
+                }
+            }
+        }
+        Ok(program)
+    }
+}
+
+/// Moves the span of `error`, an error of a file parsed on its own, to `file`.
+fn in_file(mut error: anyhow::Error, file: usize) -> anyhow::Error {
+    if let Some(e) = error.downcast_mut::<SourceError>() {
+        e.span.file = file;
+    }
+    error
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|x: char| x.is_ascii_alphabetic() || x == '_')
+        && name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_')
+}
+
+/// Module of the item `name`: what comes before its last `::`, empty for the compiled file.
+pub fn module_of(name: &str) -> &str {
+    name.rfind("::").map(|x| &name[..x]).unwrap_or("")
+}
+
+/// Full name of the item `name` used in `module`: the item of the module itself if `exists` says
+/// it has one, otherwise the item of the program named so.
+pub fn qualify(module: &str, name: &str, exists: impl Fn(&str) -> bool) -> String {
+    if !module.is_empty() {
+        let local = format!("{}::{}", module, name);
+        if exists(&local) {
+            return local;
+        }
+    }
+    name.to_owned()
+}
+
+/// Name of the item `name` in the IR, whose names can't contain `:`.
+pub fn ir_name(name: &str) -> String {
+    name.replace("::", ".")
+}
+
+impl FileElement<'_> {
+    /// Prefixes the name of the item with `module`, external functions being IR instructions
+    /// shared by every module.
+    fn prefix(&mut self, module: &str) {
+        match self {
+            FileElement::Function(a, ..)
+            | FileElement::Constant(a, ..)
+            | FileElement::Static(a, ..) => {
+                *a = Cow::Owned(format!("{}::{}", module, a));
+            }
+            FileElement::FunctionExtern(..) | FileElement::Import(..) => (),
+        }
+    }
+
+    /// Points every span of the element to `file`.
+    fn relocate(&mut self, file: usize) {
+        match self {
+            FileElement::Function(_, _, b, span) => {
+                b.relocate(file);
+                span.file = file;
+            }
+            FileElement::Constant(_, b, span) => {
+                b.relocate(file);
+                span.file = file;
+            }
+            FileElement::FunctionExtern(.., span)
+            | FileElement::Static(.., span)
+            | FileElement::Import(_, span) => span.file = file,
+        }
+    }
+}
+
+impl CodeBlock<'_> {
+    fn relocate(&mut self, file: usize) {
+        for i in &mut self.code {
+            i.relocate(file);
+        }
+    }
+}
+
+impl Instruction<'_> {
+    fn relocate(&mut self, file: usize) {
+        match self {
+            Instruction::Expression(a) => a.relocate(file),
+            Instruction::If(a, b, c, span) => {
+                a.relocate(file);
+                b.relocate(file);
+                if let Some(c) = c {
+                    c.relocate(file);
+                }
+                span.file = file;
+            }
+            Instruction::Loop(a, span) => {
+                a.relocate(file);
+                span.file = file;
+            }
+            Instruction::While(a, b, span) => {
+                a.relocate(file);
+                b.relocate(file);
+                span.file = file;
+            }
+            Instruction::For(_, a, b, c, span) => {
+                a.relocate(file);
+                b.relocate(file);
+                c.relocate(file);
+                span.file = file;
+            }
+            Instruction::Repeat(a, b, span) => {
+                a.relocate(file);
+                b.relocate(file);
+                span.file = file;
+            }
+            Instruction::Return(a, span) => {
+                if let Some(a) = a {
+                    a.relocate(file);
+                }
+                span.file = file;
+            }
+            Instruction::Assign(_, a, span) => {
+                a.relocate(file);
+                span.file = file;
+            }
+            Instruction::Store(_, a, b, span) => {
+                a.relocate(file);
+                b.relocate(file);
+                span.file = file;
+            }
+            Instruction::Let(_, _, a, span) => {
+                if let Some(a) = a {
+                    a.relocate(file);
+                }
+                span.file = file;
+            }
+            Instruction::Continue(span) | Instruction::Break(span) => span.file = file,
+        }
+    }
+}
+
+impl BooleanExpression<'_> {
+    fn relocate(&mut self, file: usize) {
+        match self {
+            BooleanExpression::Test(a, _, b) => {
+                a.relocate(file);
+                b.relocate(file);
+            }
+            BooleanExpression::Not(a) => a.relocate(file),
+            BooleanExpression::And(a, b) | BooleanExpression::Or(a, b) => {
+                a.relocate(file);
+                b.relocate(file);
+            }
+        }
+    }
+}
+
+impl Expression<'_> {
+    fn relocate(&mut self, file: usize) {
+        match self {
+            Expression::FunctionCall(_, a, span) => {
+                for i in a {
+                    i.relocate(file);
+                }
+                span.file = file;
+            }
+            Expression::Operation(_, a, b, span) => {
+                a.relocate(file);
+                b.relocate(file);
+                span.file = file;
+            }
+            Expression::Index(_, a, span)
+            | Expression::Negate(a, span)
+            | Expression::Overflow(_, a, span) => {
+                a.relocate(file);
+                span.file = file;
+            }
+            Expression::Variable(_, span) | Expression::Number(_, span) => span.file = file,
+        }
+    }
+}
@@ -1,7 +1,6 @@
 use pest::iterators::Pair;
 
 use crate::*;
-use anyhow::*;
 
 pub trait I {
     fn parse<T: ExprInto>(self) -> Result<T>;
@@ -35,7 +34,11 @@ impl<T: ExprInto> ExprInto for Option<T> {
 impl<'a> ExprInto for Cow<'a, str> {
     fn expr_into(pairs: Pair<Rule>) -> Result<Self> {
         match pairs.as_rule() {
-            Rule::literal => Ok(Cow::Owned(pairs.as_str().to_owned())),
+            Rule::literal | Rule::path => Ok(Cow::Owned(pairs.as_str().to_owned())),
+            Rule::string => {
+                let string = pairs.as_str();
+                Ok(Cow::Owned(string[1..string.len() - 1].to_owned()))
+            }
             e => Err(anyhow!("Invalid rule 0 : {:?}", e)),
         }
     }
@@ -44,7 +47,16 @@ impl<'a> ExprInto for Cow<'a, str> {
 impl ExprInto for u8 {
     fn expr_into(pairs: Pair<Rule>) -> Result<Self> {
         match pairs.as_rule() {
-            Rule::number => Ok(pairs.as_str().parse()?),
+            Rule::number => pairs
+                .as_str()
+                .parse()
+                .ok()
+                .filter(|x| *x <= 15)
+                .ok_or_else(|| {
+                    SourceError::new(pairs.as_span().into(), "number out of range")
+                        .with_note("note: numbers go from 0 to 15")
+                        .into()
+                }),
             e => Err(anyhow!("Invalid rule 1 : {:?}", e)),
         }
     }
@@ -53,14 +65,39 @@ impl ExprInto for u8 {
 impl ExprInto for BooleanExpression<'_> {
     fn expr_into(pairs: Pair<Rule>) -> Result<Self> {
         match pairs.as_rule() {
-            Rule::boolean_expr => {
+            Rule::comparison => {
                 let mut iter = pairs.into_inner();
-                Ok(BooleanExpression(
+                Ok(BooleanExpression::Test(
                     iter.next().unwrap().parse()?,
                     iter.next().unwrap().parse()?,
                     iter.next().unwrap().parse()?,
                 ))
             }
+            Rule::boolean_atom => {
+                let mut iter = pairs.into_inner();
+                let first = iter.next().unwrap();
+                if first.as_rule() == Rule::not {
+                    Ok(BooleanExpression::Not(Box::new(
+                        iter.next().unwrap().parse()?,
+                    )))
+                } else {
+                    first.parse()
+                }
+            }
+            Rule::boolean_expr | Rule::boolean_and => {
+                let or = pairs.as_rule() == Rule::boolean_expr;
+                let mut iter = pairs.into_inner();
+                let mut expression: BooleanExpression = iter.next().unwrap().parse()?;
+                for right in iter {
+                    let right = Box::new(right.parse()?);
+                    expression = if or {
+                        BooleanExpression::Or(Box::new(expression), right)
+                    } else {
+                        BooleanExpression::And(Box::new(expression), right)
+                    };
+                }
+                Ok(expression)
+            }
             e => Err(anyhow!("Invalid rule 2 : {:?}", e)),
         }
     }
@@ -70,19 +107,48 @@ impl ExprInto for Option<FileElement<'_>> {
     fn expr_into(pairs: Pair<Rule>) -> Result<Self> {
         match pairs.as_rule() {
             Rule::function => {
+                let span = pairs.as_span().into();
                 let mut iter = pairs.into_inner();
                 Ok(Some(FileElement::Function(
                     iter.next().unwrap().parse()?,
                     iter.next().unwrap().parse()?,
                     iter.next().unwrap().parse()?,
+                    span,
                 )))
             }
             Rule::EOI => Ok(None),
             Rule::extern_function => {
+                let span = pairs.as_span().into();
                 let mut iter = pairs.into_inner();
                 Ok(Some(FileElement::FunctionExtern(
                     iter.next().unwrap().parse()?,
                     iter.next().unwrap().parse()?,
+                    span,
+                )))
+            }
+            Rule::import => {
+                let span = pairs.as_span().into();
+                Ok(Some(FileElement::Import(
+                    pairs.into_inner().next().unwrap().parse()?,
+                    span,
+                )))
+            }
+            Rule::constant => {
+                let span = pairs.as_span().into();
+                let mut iter = pairs.into_inner();
+                Ok(Some(FileElement::Constant(
+                    iter.next().unwrap().parse()?,
+                    iter.next().unwrap().parse()?,
+                    span,
+                )))
+            }
+            Rule::static_variable => {
+                let span = pairs.as_span().into();
+                let mut iter = pairs.into_inner();
+                Ok(Some(FileElement::Static(
+                    iter.next().unwrap().parse()?,
+                    iter.next().unwrap().parse()?,
+                    span,
                 )))
             }
             e => Err(anyhow!("Invalid rule 4 : {:?} {}", e, pairs.as_str())),
@@ -96,6 +162,10 @@ impl ExprInto for BooleanTest {
             Rule::test => Ok(match pairs.as_str() {
                 "==" => BooleanTest::Equals,
                 "!=" => BooleanTest::NotEquals,
+                "<" => BooleanTest::Lower,
+                "<=" => BooleanTest::LowerOrEqual,
+                ">" => BooleanTest::Greater,
+                ">=" => BooleanTest::GreaterOrEqual,
                 e => return Err(anyhow!("Invalid string : {:?}", e)),
             }),
             e => Err(anyhow!("Invalid rule 5 : {:?}", e)),
@@ -110,27 +180,125 @@ impl ExprInto for Instruction<'_> {
             Rule::i_expr => Ok(Instruction::Expression(
                 pairs.into_inner().next().unwrap().parse()?,
             )),
-            Rule::i_loop => Ok(Instruction::Loop(
-                pairs.into_inner().next().unwrap().parse()?,
-            )),
-            Rule::i_return => Ok(Instruction::Return(
-                pairs.into_inner().next().unwrap().parse()?,
-            )),
-            Rule::i_continue => Ok(Instruction::Continue),
-            Rule::i_break => Ok(Instruction::Break),
+            Rule::i_loop => {
+                let span = pairs.as_span().into();
+                Ok(Instruction::Loop(
+                    pairs.into_inner().next().unwrap().parse()?,
+                    span,
+                ))
+            }
+            Rule::i_while => {
+                let span = pairs.as_span().into();
+                let mut args = pairs.into_inner();
+                Ok(Instruction::While(
+                    args.next().unwrap().parse()?,
+                    args.next().unwrap().parse()?,
+                    span,
+                ))
+            }
+            Rule::i_for => {
+                let span = pairs.as_span().into();
+                let mut args = pairs.into_inner();
+                Ok(Instruction::For(
+                    args.next().unwrap().parse()?,
+                    args.next().unwrap().parse()?,
+                    args.next().unwrap().parse()?,
+                    args.next().unwrap().parse()?,
+                    span,
+                ))
+            }
+            Rule::i_repeat => {
+                let span = pairs.as_span().into();
+                let mut args = pairs.into_inner();
+                Ok(Instruction::Repeat(
+                    args.next().unwrap().parse()?,
+                    args.next().unwrap().parse()?,
+                    span,
+                ))
+            }
+            Rule::i_return => {
+                let span = pairs.as_span().into();
+                Ok(Instruction::Return(
+                    pairs.into_inner().next().unwrap().parse()?,
+                    span,
+                ))
+            }
+            Rule::i_continue => Ok(Instruction::Continue(pairs.as_span().into())),
+            Rule::i_break => Ok(Instruction::Break(pairs.as_span().into())),
             Rule::i_assign => {
+                let span = pairs.as_span().into();
                 let mut args = pairs.into_inner();
                 Ok(Instruction::Assign(
                     args.next().unwrap().parse()?,
                     Box::new(args.next().unwrap().parse()?),
+                    span,
+                ))
+            }
+            Rule::i_let => {
+                let span = pairs.as_span().into();
+                let mut args = pairs.into_inner();
+                let name = args.next().unwrap().parse()?;
+                let ty = args.next().unwrap();
+                if ty.as_rule() == Rule::integer_type {
+                    let width = match &ty.as_str()[1..] {
+                        "4" => 1,
+                        "8" => 2,
+                        _ => 4,
+                    };
+                    return Ok(Instruction::Let(
+                        name,
+                        if ty.as_str().starts_with('i') {
+                            Type::Signed(width)
+                        } else {
+                            Type::Unsigned(width)
+                        },
+                        Some(args.next().unwrap().parse()?),
+                        span,
+                    ));
+                }
+                let length = ty.into_inner().next().unwrap();
+                Ok(Instruction::Let(
+                    name,
+                    Type::Array(
+                        length
+                            .as_str()
+                            .parse()
+                            .ok()
+                            .filter(|x| (1..=16).contains(x))
+                            .ok_or_else(|| {
+                                SourceError::new(
+                                    length.as_span().into(),
+                                    "array length out of range",
+                                )
+                                .with_note("note: arrays have 1 to 16 elements")
+                            })?,
+                    ),
+                    None,
+                    span,
+                ))
+            }
+            Rule::i_store => {
+                let span = pairs.as_span().into();
+                let mut args = pairs.into_inner();
+                let (name, index) = match args.next().unwrap().parse()? {
+                    Expression::Index(a, b, _) => (a, b),
+                    _ => unreachable!("`i_store` starts with an index"),
+                };
+                Ok(Instruction::Store(
+                    name,
+                    *index,
+                    Box::new(args.next().unwrap().parse()?),
+                    span,
                 ))
             }
             Rule::if_block => {
+                let span = pairs.as_span().into();
                 let mut args = pairs.into_inner();
                 Ok(Instruction::If(
                     args.next().unwrap().parse()?,
                     args.next().unwrap().parse()?,
                     args.next().unwrap().parse()?,
+                    span,
                 ))
             }
             e => Err(anyhow!("Invalid rule 6 : {:?}", e)),
@@ -138,6 +306,22 @@ impl ExprInto for Instruction<'_> {
     }
 }
 
+impl ExprInto for Operator {
+    fn expr_into(pairs: Pair<Rule>) -> Result<Self> {
+        match pairs.as_rule() {
+            Rule::add_op | Rule::mul_op => Ok(match pairs.as_str() {
+                "+" => Operator::Add,
+                "-" => Operator::Sub,
+                "*" => Operator::Mul,
+                "/" => Operator::Div,
+                "%" => Operator::Mod,
+                e => return Err(anyhow!("Invalid string : {:?}", e)),
+            }),
+            e => Err(anyhow!("Invalid rule 9 : {:?}", e)),
+        }
+    }
+}
+
 impl ExprInto for CodeBlock<'_> {
     fn expr_into(pairs: Pair<Rule>) -> Result<Self> {
         match pairs.as_rule() {
@@ -153,15 +337,74 @@ impl ExprInto for Expression<'_> {
     fn expr_into(pairs: Pair<Rule>) -> Result<Self> {
         match pairs.as_rule() {
             Rule::func_call => {
+                let span = pairs.as_span().into();
                 let mut args = pairs.into_inner();
                 Ok(Expression::FunctionCall(
                     args.next().unwrap().parse()?,
                     args.next().unwrap().parse()?,
+                    span,
+                ))
+            }
+            Rule::literal | Rule::path => Ok(Expression::Variable(
+                Cow::Owned(pairs.as_str().to_owned()),
+                pairs.as_span().into(),
+            )),
+            Rule::number => Ok(Expression::Number(
+                pairs.as_str().parse().map_err(|_| {
+                    SourceError::new(pairs.as_span().into(), "number out of range")
+                        .with_note("note: numbers go from 0 to 65535")
+                })?,
+                pairs.as_span().into(),
+            )),
+            Rule::index => {
+                let span = pairs.as_span().into();
+                let mut args = pairs.into_inner();
+                Ok(Expression::Index(
+                    args.next().unwrap().parse()?,
+                    Box::new(args.next().unwrap().parse()?),
+                    span,
+                ))
+            }
+            Rule::negation => {
+                let span = pairs.as_span().into();
+                Ok(Expression::Negate(
+                    Box::new(pairs.into_inner().next().unwrap().parse()?),
+                    span,
                 ))
             }
-            Rule::literal => Ok(Expression::Variable(pairs.parse()?)),
-            Rule::number => Ok(Expression::Number(pairs.parse()?)),
-            Rule::expr => pairs.into_inner().next().unwrap().parse(),
+            Rule::overflow => {
+                let span = pairs.as_span().into();
+                let mut args = pairs.into_inner();
+                let mode = match args.next().unwrap().as_str() {
+                    "checked" => Overflow::Checked,
+                    "saturating" => Overflow::Saturating,
+                    _ => Overflow::Wrapping,
+                };
+                Ok(Expression::Overflow(
+                    mode,
+                    Box::new(args.next().unwrap().parse()?),
+                    span,
+                ))
+            }
+            Rule::expr | Rule::term => {
+                let mut args = pairs.into_inner();
+                let mut expression: Expression = args.next().unwrap().parse()?;
+                while let Some(operator) = args.next() {
+                    let right: Expression = args.next().unwrap().parse()?;
+                    let span = Span {
+                        end: right.span().end,
+                        ..expression.span()
+                    };
+                    expression = Expression::Operation(
+                        operator.parse()?,
+                        Box::new(expression),
+                        Box::new(right),
+                        span,
+                    );
+                }
+                Ok(expression)
+            }
+            Rule::factor => pairs.into_inner().next().unwrap().parse(),
             e => Err(anyhow!("Invalid rule 8 : {:?}", e)),
         }
     }
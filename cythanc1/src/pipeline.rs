@@ -0,0 +1,68 @@
+use cythanc::{compile_lines, declare_lines, Origin, SourceMap, State, Template};
+
+use crate::*;
+
+/// Name given to the IR in the source map of the template code.
+const IR_FILE: &str = "<ir>";
+
+/// Every stage of a compilation, from the parsed source to the final template code.
+pub struct Compilation {
+    /// Debug dump of the parsed file.
+    pub ast: String,
+    /// The intermediate `.ct` IR handed to `cythanc`.
+    pub ir: Vec<String>,
+    /// Span of the source statement every IR line comes from.
+    pub ir_spans: Vec<Span>,
+    /// The template with the generated code inserted.
+    pub output: String,
+    /// The sections of the template holding generated code, without the rest of the template.
+    pub sections: String,
+    /// IR line every line of `output` comes from.
+    pub ir_source_map: SourceMap,
+}
+
+impl Compilation {
+    /// Source line every line of `output` comes from.
+    pub fn source_map(&self, sources: &Sources) -> SourceMap {
+        self.ir_source_map.map(|origin| {
+            let span = self.ir_spans.get(origin.line - 1)?;
+            let file = sources.file(*span);
+            let line = file.source[..span.start.min(file.source.len())]
+                .matches('\n')
+                .count()
+                + 1;
+            Some(Origin::new(&file.name, line))
+        })
+    }
+}
+
+/// Compiles a high-level program down to Cythan template code using `template`, reading the
+/// files it imports into `sources`, its `u4` arithmetic overflowing as `overflow` says.
+pub fn compile_program(
+    sources: &mut Sources,
+    template: &str,
+    overflow: Overflow,
+) -> Result<Compilation> {
+    sources.load_imports()?;
+    let elements = sources.parse()?;
+    let (ir, ir_spans) = lower(&elements, overflow)?;
+    let template = template.replace('\r', "");
+    let mut template = Template::new(&template);
+    let mut state = State::default();
+    declare_lines(IR_FILE, ir.iter().map(|x| x.as_str()), &mut state);
+    compile_lines(
+        IR_FILE,
+        ir.iter().map(|x| x.as_str()),
+        &mut state,
+        &mut template,
+    )
+    .map_err(|e| anyhow!("the generated IR failed to compile:\n{}", e))?;
+    Ok(Compilation {
+        ast: format!("{:#?}", elements),
+        ir,
+        ir_spans,
+        output: template.build(),
+        sections: template.build_sections(),
+        ir_source_map: template.build_source_map(),
+    })
+}
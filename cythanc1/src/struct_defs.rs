@@ -1,5 +1,24 @@
 use std::borrow::Cow;
 
+/// Byte range of a node in its source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    /// Index of the file in the [`Sources`](crate::Sources) of the program.
+    pub file: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Self {
+        Self {
+            file: 0,
+            start: span.start(),
+            end: span.end(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CodeBlock<'a> {
     pub code: Vec<Instruction<'a>>,
@@ -8,32 +27,257 @@ pub struct CodeBlock<'a> {
 #[derive(Debug)]
 pub enum Instruction<'a> {
     Expression(Expression<'a>),
-    If(BooleanExpression<'a>, CodeBlock<'a>, Option<CodeBlock<'a>>),
-    Loop(CodeBlock<'a>),
-    Return(Option<Expression<'a>>),
-    Assign(Cow<'a, str>, Box<Expression<'a>>),
-    Continue,
-    Break,
+    If(
+        BooleanExpression<'a>,
+        CodeBlock<'a>,
+        Option<CodeBlock<'a>>,
+        Span,
+    ),
+    Loop(CodeBlock<'a>, Span),
+    While(BooleanExpression<'a>, CodeBlock<'a>, Span),
+    /// `for i in a..b`, `b` being excluded.
+    For(
+        Cow<'a, str>,
+        Expression<'a>,
+        Expression<'a>,
+        CodeBlock<'a>,
+        Span,
+    ),
+    Repeat(Expression<'a>, CodeBlock<'a>, Span),
+    Return(Option<Expression<'a>>, Span),
+    Assign(Cow<'a, str>, Box<Expression<'a>>, Span),
+    /// `let name: type = value;`, declaring a new variable even if one is already named so, or
+    /// `let name: [u4; length];`, an array whose elements start at 0.
+    Let(Cow<'a, str>, Type, Option<Expression<'a>>, Span),
+    /// `name[index] = value;`
+    Store(Cow<'a, str>, Expression<'a>, Box<Expression<'a>>, Span),
+    Continue(Span),
+    Break(Span),
 }
 
-#[derive(Debug)]
-pub struct BooleanExpression<'a>(pub Expression<'a>, pub BooleanTest, pub Expression<'a>);
+impl Instruction<'_> {
+    pub fn span(&self) -> Span {
+        match self {
+            Instruction::Expression(e) => e.span(),
+            Instruction::If(_, _, _, e)
+            | Instruction::Loop(_, e)
+            | Instruction::While(_, _, e)
+            | Instruction::For(_, _, _, _, e)
+            | Instruction::Repeat(_, _, e)
+            | Instruction::Return(_, e)
+            | Instruction::Assign(_, _, e)
+            | Instruction::Let(_, _, _, e)
+            | Instruction::Store(_, _, _, e)
+            | Instruction::Continue(e)
+            | Instruction::Break(e) => *e,
+        }
+    }
+}
+
+/// Type of a variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    /// A number of the given number of nibbles: 1 for `u4`, 2 for `u8` and 4 for `u16`.
+    Unsigned(u8),
+    /// A two's complement number of the given number of nibbles: 1 for `i4`, 2 for `i8` and 4
+    /// for `i16`.
+    Signed(u8),
+    /// `[u4; length]`
+    Array(u8),
+}
+
+impl Type {
+    /// Number of nibbles of the type, 1 for an array.
+    pub fn width(&self) -> u8 {
+        match self {
+            Type::Unsigned(a) | Type::Signed(a) => *a,
+            Type::Array(_) => 1,
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::Signed(_))
+    }
+
+    /// Smallest number of the type, the smallest element for an array.
+    pub fn min(&self) -> i32 {
+        match self {
+            Type::Signed(a) => -(1 << (4 * a - 1)),
+            _ => 0,
+        }
+    }
+
+    /// Largest number of the type, the largest element for an array.
+    pub fn max(&self) -> i32 {
+        match self {
+            Type::Unsigned(a) => (1 << (4 * a)) - 1,
+            Type::Signed(a) => (1 << (4 * a - 1)) - 1,
+            Type::Array(_) => 15,
+        }
+    }
+
+    /// Type both `self` and `other` can be used as: the widest of the two, signed if either is.
+    ///
+    /// A signed type only holds the unsigned values narrower than itself, so mixing signedness
+    /// gives a signed type wider than the unsigned one, up to `i16` which can't hold a `u16`.
+    pub fn common(self, other: Type) -> Type {
+        let width = self.width().max(other.width());
+        match (self, other) {
+            (Type::Signed(a), b) | (b, Type::Signed(a)) if !b.is_signed() => {
+                Type::Signed(a.max((b.width() * 2).min(4)))
+            }
+            _ if self.is_signed() => Type::Signed(width),
+            _ => Type::Unsigned(width),
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Unsigned(a) => write!(f, "u{}", a * 4),
+            Type::Signed(a) => write!(f, "i{}", a * 4),
+            Type::Array(a) => write!(f, "[u4; {}]", a),
+        }
+    }
+}
 
 #[derive(Debug)]
+pub enum BooleanExpression<'a> {
+    Test(Expression<'a>, BooleanTest, Expression<'a>),
+    Not(Box<BooleanExpression<'a>>),
+    And(Box<BooleanExpression<'a>>, Box<BooleanExpression<'a>>),
+    Or(Box<BooleanExpression<'a>>, Box<BooleanExpression<'a>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BooleanTest {
     Equals,
     NotEquals,
+    Lower,
+    LowerOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl Operator {
+    /// Name of the IR instruction computing the operation.
+    pub fn instruction(&self) -> &'static str {
+        match self {
+            Operator::Add => "add",
+            Operator::Sub => "sub",
+            Operator::Mul => "mul",
+            Operator::Div => "div",
+            Operator::Mod => "mod",
+        }
+    }
+
+    /// The operator as written in the source.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Mod => "%",
+        }
+    }
+
+    /// Result of the operation on `a` and `b`, the same as the IR instruction gives.
+    pub fn apply(&self, a: u8, b: u8) -> u8 {
+        match self {
+            Operator::Add => (a + b) % 16,
+            Operator::Sub => (a + 16 - b) % 16,
+            Operator::Mul => (a * b) % 16,
+            Operator::Div => a.checked_div(b).unwrap_or(0),
+            Operator::Mod => a.checked_rem(b).unwrap_or(a),
+        }
+    }
+}
+
+/// What `u4` arithmetic does when its result is out of 0 to 15.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// The result wraps around 16.
+    #[default]
+    Wrapping,
+    /// The program stops, reporting the operation.
+    Checked,
+    /// The result stays at 0 or 15.
+    Saturating,
+}
+
+impl Overflow {
+    /// Name of the IR instruction computing `operator` in this mode.
+    pub fn instruction(&self, operator: Operator) -> String {
+        match (self, operator) {
+            (Overflow::Checked, Operator::Add | Operator::Sub | Operator::Mul) => {
+                format!("checked_{}", operator.instruction())
+            }
+            (Overflow::Saturating, Operator::Add | Operator::Sub | Operator::Mul) => {
+                format!("saturating_{}", operator.instruction())
+            }
+            _ => operator.instruction().to_owned(),
+        }
+    }
+}
+
+impl std::fmt::Display for Overflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Overflow::Wrapping => write!(f, "wrapping"),
+            Overflow::Checked => write!(f, "checked"),
+            Overflow::Saturating => write!(f, "saturating"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Expression<'a> {
-    FunctionCall(Cow<'a, str>, Vec<Expression<'a>>),
-    Variable(Cow<'a, str>),
-    Number(u8),
+    FunctionCall(Cow<'a, str>, Vec<Expression<'a>>, Span),
+    Variable(Cow<'a, str>, Span),
+    /// A number from 0 to 65535, that must fit in the type of the value it is used as.
+    Number(u16, Span),
+    /// `name[index]`, an element of an array.
+    Index(Cow<'a, str>, Box<Expression<'a>>, Span),
+    /// `-value`, the two's complement of the value.
+    Negate(Box<Expression<'a>>, Span),
+    /// `checked(value)`, the value computed with the given overflow mode.
+    Overflow(Overflow, Box<Expression<'a>>, Span),
+    Operation(Operator, Box<Expression<'a>>, Box<Expression<'a>>, Span),
+}
+
+impl Expression<'_> {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::FunctionCall(_, _, e)
+            | Expression::Variable(_, e)
+            | Expression::Number(_, e)
+            | Expression::Index(_, _, e)
+            | Expression::Negate(_, e)
+            | Expression::Overflow(_, _, e)
+            | Expression::Operation(_, _, _, e) => *e,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum FileElement<'a> {
-    Function(Cow<'a, str>, Vec<Cow<'a, str>>, CodeBlock<'a>),
-    FunctionExtern(Cow<'a, str>, Vec<Cow<'a, str>>),
+    Function(Cow<'a, str>, Vec<Cow<'a, str>>, CodeBlock<'a>, Span),
+    FunctionExtern(Cow<'a, str>, Vec<Cow<'a, str>>, Span),
+    /// `const NAME = expr;`, replaced by its value wherever it is used.
+    Constant(Cow<'a, str>, Expression<'a>, Span),
+    /// `static name = n;`, a variable shared by every function.
+    Static(Cow<'a, str>, u8, Span),
+    /// `import "file.ct";`, whose items are named `file::name`.
+    Import(Cow<'a, str>, Span),
 }
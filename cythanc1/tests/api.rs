@@ -0,0 +1,79 @@
+//! The public API of `cythanc1`, used the way other crates do.
+
+use cythanc::{compile_str, Origin, DEFAULT_TEMPLATE};
+use cythanc1::{compile_program, lower, parse, Overflow, Sources};
+
+const SOURCE: &str = "extern fn exit(exit_code);
+
+fn main() {
+    a = 2;
+    exit(a + 1);
+}";
+
+#[test]
+fn parses_and_lowers_to_ir() {
+    let elements = parse(SOURCE).unwrap();
+    assert_eq!(elements.len(), 2);
+    let (ir, spans) = lower(&elements, Overflow::Wrapping).unwrap();
+    assert_eq!(ir.len(), spans.len());
+    assert!(ir.iter().any(|x| x.starts_with("exit ")));
+    compile_str(&ir.join("\n"), DEFAULT_TEMPLATE).unwrap();
+}
+
+#[test]
+fn reports_syntax_errors() {
+    assert!(parse("fn main( {").is_err());
+}
+
+#[test]
+fn maps_template_code_back_to_source_lines() {
+    let source = "extern fn exit(exit_code);
+extern fn inc(variable);
+
+fn bump(a) {
+    inc(a);
+    return a;
+}
+
+fn main() {
+    a = 2;
+    b = bump(a);
+    exit(b);
+}";
+    let mut sources = Sources::new("main.ct", "main.ct", source);
+    let compilation = compile_program(&mut sources, DEFAULT_TEMPLATE, Overflow::Wrapping).unwrap();
+    let source_map = compilation.source_map(&sources);
+    let output: Vec<&str> = compilation.output.lines().collect();
+    let line_of = |code: &str| output.iter().position(|x| *x == code).unwrap() + 1;
+
+    // `inc(a)` in the body of `bump`, written to FUNCTION_DEF.
+    let inc = line_of("inc('var_bump@in1)");
+    let ir = compilation.ir_source_map.origin(inc).unwrap();
+    assert_eq!(ir.file, "<ir>");
+    assert_eq!(compilation.ir[ir.line - 1], "inc $a");
+    assert_eq!(source_map.origin(inc), Some(&Origin::new("main.ct", 5)));
+
+    // `exit(b)` in `main`, written to CODE.
+    let exit = line_of("exit('var_main.b)");
+    let ir = compilation.ir_source_map.origin(exit).unwrap();
+    assert_eq!(compilation.ir[ir.line - 1], "exit main.b");
+    assert_eq!(source_map.origin(exit), Some(&Origin::new("main.ct", 12)));
+
+    // Lines copied from the template come from nowhere.
+    assert_eq!(source_map.origin(1), None);
+    let entries = source_map.to_string();
+    assert!(entries.contains(&format!("{} main.ct:5", inc)));
+    assert!(entries.contains(&format!("{} main.ct:12", exit)));
+
+    let annotated: Vec<String> = source_map
+        .annotate(&compilation.output)
+        .lines()
+        .map(String::from)
+        .collect();
+    assert_eq!(annotated.len(), output.len());
+    assert_eq!(annotated[0], output[0]);
+    assert_eq!(annotated[inc - 1], "inc('var_bump@in1) # from main.ct:5");
+    assert_eq!(annotated[exit - 1], "exit('var_main.b) # from main.ct:12");
+    // Only the first line coming from a statement is annotated.
+    assert_eq!(annotated[exit - 2], output[exit - 2]);
+}
@@ -0,0 +1,249 @@
+//! Golden tests: every case of `tests/golden` is compiled to template code, whose generated
+//! sections are compared with its `.out.ct` snapshot, the rest being the fixed template. It is
+//! then assembled, run and must exit with the expected value, its arithmetic wrapping unless the
+//! case names another overflow mode. Cases that must not compile, in that mode too, have their
+//! error compared with a `.err` snapshot instead, and trap cases, compiled with checked
+//! arithmetic, must stop in a trap whose message is compared, located at a line of the source.
+//!
+//! Run them with `UPDATE_SNAPSHOTS=1` to rewrite the snapshots after an intended output change.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use cythanc::{
+    assembler::assemble, compile_lines, declare_lines, vm, State, Template, DEFAULT_TEMPLATE,
+};
+use cythanc1::{compile_program, render_error, Compilation, Overflow, Sources};
+
+/// Language a case is written in.
+enum Kind {
+    /// High-level source compiled by `cythanc1`.
+    Source,
+    /// IR compiled by `cythanc`.
+    Ir,
+}
+
+macro_rules! golden {
+    ($($name:ident: $kind:ident $file:literal $(with $mode:ident)? => $exit:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                check(Kind::$kind, $file, overflow!($($mode)?), $exit);
+            }
+        )*
+    };
+}
+
+/// Overflow mode a case is compiled with, wrapping unless the case gives one.
+macro_rules! overflow {
+    () => {
+        Overflow::Wrapping
+    };
+    ($mode:ident) => {
+        Overflow::$mode
+    };
+}
+
+macro_rules! errors {
+    ($($name:ident: $kind:ident $file:literal $(with $mode:ident)?,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                check_error(Kind::$kind, $file, overflow!($($mode)?));
+            }
+        )*
+    };
+}
+
+macro_rules! traps {
+    ($($name:ident: $file:literal => $message:literal,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                check_trap($file, $message);
+            }
+        )*
+    };
+}
+
+golden! {
+    main: Source "main.ct" => 11,
+    calls: Source "calls.ct" => 5,
+    conditions: Source "conditions.ct" => 4,
+    loops: Source "loops.ct" => 8,
+    arithmetic: Source "arithmetic.ct" => 2,
+    comparisons: Source "comparisons.ct" => 11,
+    counted_loops: Source "counted_loops.ct" => 7,
+    scopes: Source "scopes.ct" => 12,
+    recursion: Source "recursion.ct" => 14,
+    hoisting: Source "hoisting.ct" => 9,
+    mutual_recursion: Source "mutual_recursion.ct" => 5,
+    globals: Source "globals.ct" => 11,
+    imports: Source "imports.ct" => 9,
+    arrays: Source "arrays.ct" => 14,
+    wide: Source "wide.ct" => 15,
+    overflow: Source "overflow.ct" => 7,
+    signed: Source "signed.ct" => -8,
+    mixed_sign: Source "mixed_sign.ct" => 7,
+    equality_checked: Source "equality.ct" with Checked => 3,
+    equality_saturating: Source "equality.ct" with Saturating => 3,
+    ir_functions: Ir "ir_functions.ct" => 2,
+    ir_jumps: Ir "ir_jumps.ct" => 7,
+    ir_arithmetic: Ir "ir_arithmetic.ct" => 5,
+    ir_recursion: Ir "ir_recursion.ct" => 8,
+    ir_forward: Ir "ir_forward.ct" => 10,
+    ir_arrays: Ir "ir_arrays.ct" => 12,
+    ir_wide: Ir "ir_wide.ct" => 9,
+    ir_signed: Ir "ir_signed.ct" => -5,
+    ir_overflow: Ir "ir_overflow.ct" => 8,
+    ir_parameters: Ir "ir_parameters.ct" => 5,
+    ir_scopes: Ir "ir_scopes.ct" => 12,
+    ir_labels: Ir "ir_labels.ct" => 3,
+}
+
+errors! {
+    undeclared: Source "undeclared.ct",
+    duplicate: Source "duplicate.ct",
+    assign_constant: Source "assign_constant.ct",
+    import_cycle: Source "import_cycle.ct",
+    array_as_number: Source "array_as_number.ct",
+    wide_argument: Source "wide_argument.ct",
+    signed_to_unsigned: Source "signed_to_unsigned.ct",
+    unsigned_to_signed: Source "unsigned_to_signed.ct",
+    checked_wide: Source "checked_wide.ct",
+    checked_u8: Source "checked_u8.ct" with Checked,
+    checked_i4: Source "checked_i4.ct" with Checked,
+    overflow_mode_name: Source "overflow_mode_name.ct",
+    index_out_of_bounds: Source "index_out_of_bounds.ct",
+    load_out_of_bounds: Source "load_out_of_bounds.ct",
+    ir_unknown_parameter: Ir "ir_unknown_parameter.ct",
+    ir_duplicate: Ir "ir_duplicate.ct",
+    ir_undefined_label: Ir "ir_undefined_label.ct",
+    ir_undeclared: Ir "ir_undeclared.ct",
+    ir_call_cycle: Ir "ir_call_cycle.ct",
+    ir_array_length: Ir "ir_array_length.ct",
+}
+
+traps! {
+    checked_overflow: "checked_overflow.ct" => "arithmetic overflow at checked_overflow.ct:6",
+    stack_overflow: "stack_overflow.ct" => "stack overflow at stack_overflow.ct:5",
+}
+
+fn read_case(file: &str) -> (PathBuf, String) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(file);
+    let source = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+        .replace('\r', "");
+    (path, source)
+}
+
+/// Compiles the case `file` of the high-level language, whose imports are read next to it.
+fn compile_case(
+    file: &str,
+    path: &Path,
+    source: &str,
+    overflow: Overflow,
+) -> (Sources, anyhow::Result<Compilation>) {
+    let mut sources = Sources::new(file, path, source);
+    let output = compile_program(&mut sources, DEFAULT_TEMPLATE, overflow);
+    (sources, output)
+}
+
+/// Compiles the IR case `source` into the built template and its generated sections.
+fn compile_ir(source: &str) -> Result<(String, String), cythanc::Error> {
+    let mut template = Template::new(DEFAULT_TEMPLATE);
+    let mut state = State::default();
+    declare_lines("<input>", source.lines(), &mut state);
+    compile_lines("<input>", source.lines(), &mut state, &mut template)?;
+    Ok((template.build(), template.build_sections()))
+}
+
+fn check(kind: Kind, file: &str, overflow: Overflow, exit: i8) {
+    let (path, source) = read_case(file);
+    let (output, sections) = match kind {
+        Kind::Source => {
+            let (sources, output) = compile_case(file, &path, &source, overflow);
+            let compilation = output.unwrap_or_else(|e| panic!("{}", render_error(&e, &sources)));
+            (compilation.output, compilation.sections)
+        }
+        Kind::Ir => compile_ir(&source).unwrap_or_else(|e| panic!("{}", e)),
+    };
+    compare_snapshot(&path.with_extension("out.ct"), &sections);
+    let image = assemble(file, &output).unwrap_or_else(|e| panic!("{}", e));
+    match vm::run(&image, vm::DEFAULT_MAX_CYCLES) {
+        Ok(Some(e)) => assert_eq!(e, exit, "{} exited with the wrong value", file),
+        Ok(None) => panic!("{} stopped without an exit value", file),
+        Err(e) => panic!("{}: {}", file, e),
+    }
+}
+
+fn check_error(kind: Kind, file: &str, overflow: Overflow) {
+    let (path, source) = read_case(file);
+    let error = match kind {
+        Kind::Source => match compile_case(file, &path, &source, overflow) {
+            (_, Ok(_)) => panic!("{} compiled but should not", file),
+            (sources, Err(e)) => render_error(&e, &sources),
+        },
+        Kind::Ir => match compile_ir(&source) {
+            Ok(_) => panic!("{} compiled but should not", file),
+            Err(e) => e.to_string(),
+        },
+    };
+    compare_snapshot(&path.with_extension("err"), &error);
+}
+
+/// Compiles the case `file` with checked arithmetic and runs it, expecting it to stop in a trap
+/// described as `message`.
+fn check_trap(file: &str, message: &str) {
+    let (path, source) = read_case(file);
+    let (sources, compilation) = compile_case(file, &path, &source, Overflow::Checked);
+    let compilation = compilation.unwrap_or_else(|e| panic!("{}", render_error(&e, &sources)));
+    let image = assemble(file, &compilation.output).unwrap_or_else(|e| panic!("{}", e));
+    match vm::run(&image, vm::DEFAULT_MAX_CYCLES) {
+        Err(e @ (vm::RunError::Overflow { .. } | vm::RunError::StackOverflow { .. })) => {
+            assert_eq!(e.describe(&compilation.source_map(&sources)), message)
+        }
+        Ok(e) => panic!("{} exited with {:?} instead of stopping in a trap", file, e),
+        Err(e) => panic!("{}: {}", file, e),
+    }
+}
+
+fn compare_snapshot(path: &Path, output: &str) {
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(path, output).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        return;
+    }
+    let expected = fs::read_to_string(path)
+        .unwrap_or_else(|e| {
+            panic!(
+                "{}: {} (run with `UPDATE_SNAPSHOTS=1` to create it)",
+                path.display(),
+                e
+            )
+        })
+        .replace('\r', "");
+    if let Some((line, (expected, found))) = expected
+        .lines()
+        .zip(output.lines())
+        .enumerate()
+        .find(|(_, (a, b))| a != b)
+    {
+        panic!(
+            "{} differs at line {}:\nexpected: {}\n   found: {}\n(run with `UPDATE_SNAPSHOTS=1` to accept the new output)",
+            path.display(),
+            line + 1,
+            expected,
+            found
+        );
+    }
+    assert_eq!(
+        expected.lines().count(),
+        output.lines().count(),
+        "{} does not have the expected length (run with `UPDATE_SNAPSHOTS=1` to accept the new output)",
+        path.display()
+    );
+}
@@ -0,0 +1,580 @@
+//! Assembler turning template code (the output of [`Template::build`](crate::Template::build))
+//! into the flat memory image a Cythan machine runs.
+//!
+//! The syntax is the one of `template.ct`: numbers, `'label` references, `'label:value`
+//! definitions, `~+N`/`~-N` addresses relative to the cell they are written in, macro
+//! definitions (`name { ... }` or `name = (...)`) and calls (`name(args)` or just `name`), with
+//! `self.N`, `self.N..`, `self..` and `---` referring to the arguments of the current macro.
+//!
+//! Labels defined inside a macro only exist in the expansion that defines them, except the ones
+//! starting with `#` (`'#0`, `'#return_0`, ...) which are always global.
+
+use std::collections::HashMap;
+
+use crate::{closest, Diagnostic, Error};
+
+/// Maximum nesting of macro calls, reached by recursive macros.
+const MAX_DEPTH: usize = 64;
+
+/// Memory of a Cythan machine, cell 0 being the instruction pointer.
+pub struct Image {
+    pub memory: Vec<usize>,
+    /// Addresses of the labels defined outside of macros.
+    pub labels: HashMap<String, usize>,
+    /// One-based line of the assembled source every cell comes from.
+    pub lines: Vec<usize>,
+}
+
+impl Image {
+    /// Address of a global label such as `var_a` (written without the leading `'`).
+    pub fn label(&self, name: &str) -> Option<usize> {
+        self.labels.get(name).copied()
+    }
+
+    /// Global labels sorted by address.
+    pub fn sorted_labels(&self) -> Vec<(&str, usize)> {
+        let mut labels: Vec<_> = self.labels.iter().map(|(a, b)| (a.as_str(), *b)).collect();
+        labels.sort_by_key(|(a, b)| (*b, *a));
+        labels
+    }
+
+    /// The memory as space separated numbers.
+    pub fn to_numbers(&self) -> String {
+        self.memory
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Listing of the image: the cells produced by every line of `source`, preceded by their
+    /// address, followed by the address of every global label.
+    pub fn listing(&self, source: &str) -> String {
+        let lines: Vec<&str> = source.lines().collect();
+        let mut out = Vec::new();
+        let mut start = 0;
+        while start < self.memory.len() {
+            let line = self.lines[start];
+            let end = (start..self.memory.len())
+                .find(|x| self.lines[*x] != line)
+                .unwrap_or(self.memory.len());
+            out.push(format!(
+                "{:>6} | {:>5} | {}",
+                start,
+                line,
+                lines.get(line - 1).map(|x| x.trim()).unwrap_or("")
+            ));
+            out.push(format!(
+                "       |       |     {}",
+                self.memory[start..end]
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ));
+            start = end;
+        }
+        out.push(String::new());
+        out.push("labels:".to_owned());
+        for (name, address) in self.sorted_labels() {
+            out.push(format!("{:>6} | '{}", address, name));
+        }
+        out.join("\n")
+    }
+}
+
+/// Assembles template code, `file` being the name used in diagnostics.
+pub fn assemble(file: &str, source: &str) -> Result<Image, Error> {
+    let mut diagnostics = Vec::new();
+    let tokens = tokenize(source);
+    let (items, macros) = match Parser::new(&tokens).parse_file() {
+        Ok(e) => e,
+        Err((line, message)) => {
+            return Err(Error {
+                diagnostics: vec![line_error(file, source, line, message)],
+            })
+        }
+    };
+    let mut assembler = Assembler {
+        macros,
+        memory: Vec::new(),
+        lines: Vec::new(),
+        scopes: vec![HashMap::new()],
+        errors: Vec::new(),
+    };
+    for item in &items {
+        assembler.expand(item, item.line, &[], 0, 0);
+    }
+    let Assembler {
+        memory,
+        lines,
+        scopes,
+        errors,
+        ..
+    } = assembler;
+    let memory = memory
+        .into_iter()
+        .zip(&lines)
+        .map(|(cell, line)| match cell {
+            Pending::Value(e) => e,
+            Pending::Label(scope, name) => {
+                match scopes[scope].get(&name).or_else(|| scopes[0].get(&name)) {
+                    Some(e) => *e,
+                    None => {
+                        let message = format!("undefined label `'{}`", name);
+                        let diagnostic = line_error(file, source, *line, message);
+                        diagnostics.push(
+                            match closest(&name, scopes[0].keys().map(|x| x.as_str())) {
+                                Some(e) => {
+                                    diagnostic.with_note(format!("help: did you mean `'{}`?", e))
+                                }
+                                None => diagnostic,
+                            },
+                        );
+                        0
+                    }
+                }
+            }
+        })
+        .collect();
+    diagnostics.extend(
+        errors
+            .into_iter()
+            .map(|(line, message)| line_error(file, source, line, message)),
+    );
+    if diagnostics.is_empty() {
+        Ok(Image {
+            memory,
+            labels: scopes.into_iter().next().unwrap(),
+            lines,
+        })
+    } else {
+        diagnostics.sort_by_key(|x| x.line);
+        diagnostics.dedup();
+        Err(Error { diagnostics })
+    }
+}
+
+fn line_error(file: &str, source: &str, line: usize, message: String) -> Diagnostic {
+    Diagnostic::error(message)
+        .on_token(source.lines().nth(line - 1).unwrap_or(""), None)
+        .in_file(file, line)
+}
+
+/// Splits the source in tokens with their line, `(`, `)`, `{`, `}` and `=` being tokens of their
+/// own and a `#` at the start of a token commenting the rest of the line.
+fn tokenize(source: &str) -> Vec<(&str, usize)> {
+    let mut tokens = Vec::new();
+    for (line, text) in source.lines().enumerate() {
+        let mut start = None;
+        for (i, c) in text.char_indices() {
+            if c == '#' && start.is_none() {
+                break;
+            }
+            if c.is_whitespace() || "(){}=".contains(c) {
+                if let Some(s) = start.take() {
+                    tokens.push((&text[s..i], line + 1));
+                }
+                if !c.is_whitespace() {
+                    tokens.push((&text[i..i + c.len_utf8()], line + 1));
+                }
+            } else if start.is_none() {
+                start = Some(i);
+            }
+        }
+        if let Some(s) = start {
+            tokens.push((&text[s..], line + 1));
+        }
+    }
+    tokens
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Number(usize),
+    Label(String),
+    Define(String, Box<Located>),
+    Relative(isize),
+    Argument(usize),
+    Spread(usize),
+    Call(String, Vec<Located>),
+}
+
+#[derive(Debug, Clone)]
+struct Located {
+    item: Item,
+    line: usize,
+}
+
+type ParseError = (usize, String);
+
+type Macros = HashMap<String, Vec<Located>>;
+
+struct Parser<'a> {
+    tokens: &'a [(&'a str, usize)],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [(&'a str, usize)]) -> Self {
+        Self {
+            tokens,
+            position: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).map(|x| x.0)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position.min(self.tokens.len().saturating_sub(1)))
+            .map(|x| x.1)
+            .unwrap_or(1)
+    }
+
+    fn next(&mut self) -> Option<(&'a str, usize)> {
+        let token = self.tokens.get(self.position).copied();
+        self.position += 1;
+        token
+    }
+
+    /// Parses the top level, made of items and macro definitions.
+    fn parse_file(mut self) -> Result<(Vec<Located>, Macros), ParseError> {
+        let mut items = Vec::new();
+        let mut macros = HashMap::new();
+        while let Some((token, line)) = self.next() {
+            let end = match self.peek() {
+                Some("{") if is_name(token) => "}",
+                Some("=") if is_name(token) => ")",
+                _ => {
+                    self.position -= 1;
+                    items.push(self.parse_item()?);
+                    continue;
+                }
+            };
+            if self.next().map(|x| x.0) == Some("=") && self.next().map(|x| x.0) != Some("(") {
+                return Err((line, format!("expected `(` after `{} =`", token)));
+            }
+            let mut body = Vec::new();
+            loop {
+                match self.peek() {
+                    Some(e) if e == end => {
+                        self.position += 1;
+                        break;
+                    }
+                    Some(_) => body.push(self.parse_item()?),
+                    None => {
+                        return Err((line, format!("`{}` is never closed with `{}`", token, end)))
+                    }
+                }
+            }
+            if macros.insert(token.to_owned(), body).is_some() {
+                return Err((line, format!("macro `{}` is defined twice", token)));
+            }
+        }
+        Ok((items, macros))
+    }
+
+    fn parse_item(&mut self) -> Result<Located, ParseError> {
+        let line = self.line();
+        let (token, _) = self
+            .next()
+            .ok_or((line, "unexpected end of file".to_owned()))?;
+        self.parse_token(token, line)
+    }
+
+    fn parse_token(&mut self, token: &'a str, line: usize) -> Result<Located, ParseError> {
+        let item = if let Some(label) = token.strip_prefix('\'') {
+            match label.split_once(':') {
+                Some((name, "")) => {
+                    return Err((line, format!("missing value after `'{}:`", name)));
+                }
+                Some((name, value)) => {
+                    Item::Define(name.to_owned(), Box::new(self.parse_token(value, line)?))
+                }
+                None => Item::Label(label.to_owned()),
+            }
+        } else if let Ok(e) = token.parse() {
+            Item::Number(e)
+        } else if let Some(offset) = token.strip_prefix('~') {
+            Item::Relative(
+                offset
+                    .strip_prefix('+')
+                    .unwrap_or(offset)
+                    .parse()
+                    .map_err(|_| (line, format!("invalid relative address `{}`", token)))?,
+            )
+        } else if token == "---" || token == "self.." {
+            Item::Spread(0)
+        } else if let Some(argument) = token.strip_prefix("self.") {
+            let (number, spread) = match argument.strip_suffix("..") {
+                Some(e) => (e, true),
+                None => (argument, false),
+            };
+            let number = number
+                .parse()
+                .map_err(|_| (line, format!("invalid argument `{}`", token)))?;
+            if spread {
+                Item::Spread(number)
+            } else {
+                Item::Argument(number)
+            }
+        } else if is_name(token) {
+            let mut arguments = Vec::new();
+            if self.peek() == Some("(") {
+                self.position += 1;
+                loop {
+                    match self.peek() {
+                        Some(")") => {
+                            self.position += 1;
+                            break;
+                        }
+                        Some(_) => arguments.push(self.parse_item()?),
+                        None => return Err((line, format!("`{}(` is never closed", token))),
+                    }
+                }
+            }
+            Item::Call(token.to_owned(), arguments)
+        } else {
+            return Err((line, format!("unexpected `{}`", token)));
+        };
+        Ok(Located { item, line })
+    }
+}
+
+fn is_name(token: &str) -> bool {
+    token
+        .chars()
+        .next()
+        .map(|x| x.is_alphabetic() || x == '_')
+        .unwrap_or(false)
+        && token.chars().all(|x| x.is_alphanumeric() || x == '_')
+}
+
+/// A value passed as macro argument, placed when the macro uses it.
+#[derive(Debug, Clone)]
+enum Operand {
+    Number(usize),
+    Label(usize, String),
+    Relative(isize),
+}
+
+/// A cell whose value is known or will be once every label is defined.
+enum Pending {
+    Value(usize),
+    Label(usize, String),
+}
+
+struct Assembler {
+    macros: Macros,
+    memory: Vec<Pending>,
+    lines: Vec<usize>,
+    /// Labels of every macro expansion, the first one being the global scope.
+    scopes: Vec<HashMap<String, usize>>,
+    errors: Vec<ParseError>,
+}
+
+impl Assembler {
+    /// Emits the cells of `item`, `line` being the top level line it comes from.
+    fn expand(
+        &mut self,
+        item: &Located,
+        line: usize,
+        arguments: &[Operand],
+        scope: usize,
+        depth: usize,
+    ) {
+        match &item.item {
+            Item::Number(e) => self.push(Operand::Number(*e), line),
+            Item::Label(e) => self.push(Operand::Label(label_scope(e, scope), e.clone()), line),
+            Item::Relative(e) => self.push(Operand::Relative(*e), line),
+            Item::Argument(e) => match arguments.get(*e) {
+                Some(e) => self.push(e.clone(), line),
+                None => self.error(line, format!("no argument `self.{}` given", e)),
+            },
+            Item::Spread(e) => {
+                for argument in arguments.iter().skip(*e) {
+                    self.push(argument.clone(), line);
+                }
+            }
+            Item::Define(name, value) => {
+                let address = self.memory.len();
+                let name_scope = label_scope(name, scope);
+                if self.scopes[name_scope]
+                    .insert(name.clone(), address)
+                    .is_some()
+                {
+                    self.error(line, format!("label `'{}` is defined twice", name));
+                }
+                self.expand(value, line, arguments, scope, depth);
+            }
+            Item::Call(name, call_arguments) => {
+                if depth >= MAX_DEPTH {
+                    self.error(
+                        line,
+                        format!("`{}` expands too deeply, is it recursive?", name),
+                    );
+                    return;
+                }
+                let mut operands = Vec::new();
+                for argument in call_arguments {
+                    match &argument.item {
+                        Item::Number(e) => operands.push(Operand::Number(*e)),
+                        Item::Label(e) => {
+                            operands.push(Operand::Label(label_scope(e, scope), e.clone()))
+                        }
+                        Item::Relative(e) => operands.push(Operand::Relative(*e)),
+                        Item::Argument(e) => match arguments.get(*e) {
+                            Some(e) => operands.push(e.clone()),
+                            None => self.error(line, format!("no argument `self.{}` given", e)),
+                        },
+                        Item::Spread(e) => operands.extend(arguments.iter().skip(*e).cloned()),
+                        Item::Define(..) | Item::Call(..) => self.error(
+                            line,
+                            format!("arguments of `{}` must be single values", name),
+                        ),
+                    }
+                }
+                let body = match self.macros.get(name) {
+                    Some(e) => e.clone(),
+                    None => {
+                        let message = match closest(name, self.macros.keys().map(|x| x.as_str())) {
+                            Some(e) => format!("unknown macro `{}`, did you mean `{}`?", name, e),
+                            None => format!("unknown macro `{}`", name),
+                        };
+                        self.error(line, message);
+                        return;
+                    }
+                };
+                self.scopes.push(HashMap::new());
+                let inner = self.scopes.len() - 1;
+                for item in &body {
+                    self.expand(item, line, &operands, inner, depth + 1);
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, operand: Operand, line: usize) {
+        let address = self.memory.len();
+        let cell = match operand {
+            Operand::Number(e) => Pending::Value(e),
+            Operand::Label(scope, name) => Pending::Label(scope, name),
+            Operand::Relative(e) => match address.checked_add_signed(e) {
+                Some(e) => Pending::Value(e),
+                None => {
+                    self.error(line, format!("`~{}` points before the first cell", e));
+                    Pending::Value(0)
+                }
+            },
+        };
+        self.memory.push(cell);
+        self.lines.push(line);
+    }
+
+    fn error(&mut self, line: usize, message: String) {
+        self.errors.push((line, message));
+    }
+}
+
+/// Scope a label belongs to, labels starting with `#` being global.
+fn label_scope(name: &str, scope: usize) -> usize {
+    if name.starts_with('#') {
+        0
+    } else {
+        scope
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(source: &str) -> Vec<usize> {
+        assemble("test.ct", source).unwrap().memory
+    }
+
+    /// Messages and lines of the diagnostics assembling `source` fails with.
+    fn errors(source: &str) -> Vec<(usize, String)> {
+        assemble("test.ct", source)
+            .err()
+            .unwrap()
+            .diagnostics
+            .into_iter()
+            .map(|x| (x.line, x.message))
+            .collect()
+    }
+
+    #[test]
+    fn scopes_labels_to_their_expansion() {
+        assert_eq!(memory("m { 'a:7 'a }\nm\nm"), [7, 0, 7, 2]);
+        let error = assemble("test.ct", "m { 'a:7 }\nm\n'b").err().unwrap();
+        assert_eq!(error.diagnostics[0].message, "undefined label `'b`");
+        assert!(assemble("test.ct", "m { 'a:7 }\nm\n'a").is_err());
+    }
+
+    #[test]
+    fn keeps_hash_labels_global() {
+        let image = assemble("test.ct", "m { '#g:5 }\n1 m\n'#g").unwrap();
+        assert_eq!(image.memory, [1, 5, 1]);
+        assert_eq!(image.label("#g"), Some(1));
+        assert_eq!(
+            errors("m { '#g:5 }\nm\nm"),
+            [(3, "label `'#g` is defined twice".to_owned())]
+        );
+    }
+
+    #[test]
+    fn resolves_relative_addresses() {
+        assert_eq!(memory("9 ~+1 ~-1 ~0"), [9, 2, 1, 3]);
+        assert_eq!(memory("m { 0 ~+2 }\n5 m(~-1)"), [5, 0, 4]);
+        assert_eq!(
+            errors("1\n~-2"),
+            [(2, "`~-2` points before the first cell".to_owned())]
+        );
+    }
+
+    #[test]
+    fn spreads_arguments() {
+        assert_eq!(memory("m { --- }\nm(1 2 3)"), [1, 2, 3]);
+        assert_eq!(memory("m { self.. }\nm(1 2)"), [1, 2]);
+        assert_eq!(memory("m { self.1.. self.0 }\nm(1 2 3)"), [2, 3, 1]);
+        assert_eq!(memory("m { self.2.. }\nm(1)"), []);
+        assert_eq!(
+            memory("n { --- 0 }\nm { n(self.1..) }\nm(1 2 3)"),
+            [2, 3, 0]
+        );
+    }
+
+    #[test]
+    fn reports_undefined_labels() {
+        let error = assemble("test.ct", "'loop:0\n0\n'lop").err().unwrap();
+        assert_eq!(error.diagnostics.len(), 1);
+        let diagnostic = &error.diagnostics[0];
+        assert_eq!(diagnostic.message, "undefined label `'lop`");
+        assert_eq!((diagnostic.file.as_str(), diagnostic.line), ("test.ct", 3));
+        assert_eq!(diagnostic.notes, ["help: did you mean `'loop`?"]);
+    }
+
+    #[test]
+    fn stops_recursive_macros() {
+        assert_eq!(
+            errors("m { 0 m }\n1\nm"),
+            [(3, "`m` expands too deeply, is it recursive?".to_owned())]
+        );
+        let deep = (0..MAX_DEPTH)
+            .map(|x| format!("m{} {{ m{} }}", x, x + 1))
+            .chain([format!("m{} {{ 1 }}", MAX_DEPTH), "m0".to_owned()])
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert!(assemble("test.ct", &deep).is_err());
+        let shallow = (0..MAX_DEPTH - 1)
+            .map(|x| format!("m{} {{ m{} }}", x, x + 1))
+            .chain([format!("m{} {{ 1 }}", MAX_DEPTH - 1), "m0".to_owned()])
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(memory(&shallow), [1]);
+    }
+}
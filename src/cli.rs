@@ -0,0 +1,247 @@
+//! Command-line options shared by the `cythanc` and `cythanc1` drivers.
+
+use crate::{
+    io::EXIT_RUNTIME,
+    vm::{RunError, DEFAULT_MAX_CYCLES},
+    SourceMap,
+};
+
+/// Exit codes of both drivers, printed at the end of their usage.
+pub const EXIT_CODES: &str = "Exit codes:
+  0  success
+  1  the program failed to compile
+  2  invalid command line
+  3  an input or output file could not be read or written
+  4  the program run with `--run` did not exit with a value: it did not stop
+     within the cycle limit, stopped in a trap or without an exit value";
+
+/// What a driver writes when it does not run the program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    /// The whole template with the generated code inserted.
+    Template,
+    /// Only the generated `# header` sections, without the template around them.
+    Sections,
+    /// The intermediate IR the program was lowered to.
+    Ir,
+    /// The assembled memory image.
+    Image,
+    /// The assembled memory image annotated with addresses and template lines.
+    Listing,
+}
+
+impl Emit {
+    /// Name of the kind as given to `--emit`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Emit::Template => "template",
+            Emit::Sections => "sections",
+            Emit::Ir => "ir",
+            Emit::Image => "image",
+            Emit::Listing => "listing",
+        }
+    }
+}
+
+pub struct Options {
+    pub inputs: Vec<String>,
+    pub output: String,
+    pub template: Option<String>,
+    pub emit: Emit,
+    pub source_map: Option<String>,
+    pub annotate: bool,
+    /// Run the program instead of writing it.
+    pub run: bool,
+    pub debug: bool,
+    pub max_cycles: u64,
+    pub help: bool,
+}
+
+impl Options {
+    /// Parses `args`, `--emit` taking one of `emits`.
+    ///
+    /// Options of a single driver go to `extra` along with a function reading their value, and
+    /// are unknown unless it returns `true`.
+    pub fn parse(
+        args: impl IntoIterator<Item = String>,
+        emits: &[Emit],
+        mut extra: impl FnMut(&str, &mut dyn FnMut() -> Result<String, String>) -> Result<bool, String>,
+    ) -> Result<Self, String> {
+        let mut options = Options {
+            inputs: Vec::new(),
+            output: "-".to_owned(),
+            template: None,
+            emit: Emit::Template,
+            source_map: None,
+            annotate: false,
+            run: false,
+            debug: false,
+            max_cycles: DEFAULT_MAX_CYCLES,
+            help: false,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("missing value for `{}`", name))
+            };
+            match arg.as_str() {
+                "-h" | "--help" => options.help = true,
+                "-o" | "--output" => options.output = value(&arg)?,
+                "-t" | "--template" => options.template = Some(value(&arg)?),
+                "--emit" => {
+                    let value = value(&arg)?;
+                    options.emit = *emits
+                        .iter()
+                        .find(|x| x.name() == value)
+                        .ok_or_else(|| format!("unknown emit kind `{}`", value))?;
+                }
+                "--source-map" => options.source_map = Some(value(&arg)?),
+                "--annotate" => options.annotate = true,
+                "--run" => options.run = true,
+                "--debug" => options.debug = true,
+                "--max-cycles" => {
+                    let value = value(&arg)?;
+                    options.max_cycles = value
+                        .parse()
+                        .map_err(|_| format!("invalid cycle count `{}`", value))?;
+                }
+                "-" => options.inputs.push(arg),
+                e if e.starts_with('-') => {
+                    if !extra(e, &mut || value(e))? {
+                        return Err(format!("unknown option `{}`", e));
+                    }
+                }
+                _ => options.inputs.push(arg),
+            }
+        }
+        if options.inputs.is_empty() {
+            options.inputs.push("-".to_owned());
+        }
+        if options.debug && options.inputs.iter().any(|x| x == "-") {
+            return Err(
+                "`--debug` reads commands from stdin, the program must be a file".to_owned(),
+            );
+        }
+        Ok(options)
+    }
+}
+
+/// What `--run` writes for `result`, the end of a run, or the exit code and message it fails
+/// with, a trap being located with `map`.
+pub fn run_output(
+    result: Result<Option<i8>, RunError>,
+    map: &SourceMap,
+) -> Result<String, (i32, String)> {
+    match result {
+        Ok(Some(e)) => Ok(e.to_string()),
+        Ok(None) => Err((
+            EXIT_RUNTIME,
+            "the program stopped without an exit value".to_owned(),
+        )),
+        Err(e) => Err((EXIT_RUNTIME, e.describe(map))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMITS: [Emit; 4] = [Emit::Template, Emit::Sections, Emit::Image, Emit::Listing];
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|x| x.to_string()), &EMITS, |_, _| Ok(false))
+    }
+
+    #[test]
+    fn defaults_to_stdin_and_stdout() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.inputs, ["-"]);
+        assert_eq!(options.output, "-");
+        assert_eq!(options.emit, Emit::Template);
+    }
+
+    #[test]
+    fn dash_is_an_input() {
+        let options = parse(&["a.ct", "-", "-o", "-"]).unwrap();
+        assert_eq!(options.inputs, ["a.ct", "-"]);
+        assert_eq!(options.output, "-");
+    }
+
+    #[test]
+    fn keeps_every_input_in_order() {
+        let options = parse(&["b.ct", "a.ct", "-o", "out.ct", "c.ct"]).unwrap();
+        assert_eq!(options.inputs, ["b.ct", "a.ct", "c.ct"]);
+        assert_eq!(options.output, "out.ct");
+    }
+
+    #[test]
+    fn parses_every_emit_kind() {
+        for emit in EMITS {
+            assert_eq!(parse(&["--emit", emit.name()]).unwrap().emit, emit);
+        }
+        assert_eq!(
+            parse(&["--emit", "ir"]).err().unwrap(),
+            "unknown emit kind `ir`"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        assert_eq!(
+            parse(&["--frobnicate"]).err().unwrap(),
+            "unknown option `--frobnicate`"
+        );
+    }
+
+    #[test]
+    fn rejects_missing_values() {
+        assert_eq!(
+            parse(&["in.ct", "-o"]).err().unwrap(),
+            "missing value for `-o`"
+        );
+        assert_eq!(
+            parse(&["--max-cycles", "many"]).err().unwrap(),
+            "invalid cycle count `many`"
+        );
+    }
+
+    #[test]
+    fn gives_driver_options_to_extra() {
+        let mut seen = Vec::new();
+        let options = Options::parse(
+            ["--mode", "fast", "in.ct", "--flag"].map(String::from),
+            &EMITS,
+            |arg, value| {
+                seen.push(match arg {
+                    "--mode" => value()?,
+                    _ => arg.to_owned(),
+                });
+                Ok(true)
+            },
+        )
+        .unwrap();
+        assert_eq!(options.inputs, ["in.ct"]);
+        assert_eq!(seen, ["fast", "--flag"]);
+    }
+
+    #[test]
+    fn debugs_files_only() {
+        assert!(parse(&["--debug", "in.ct"]).is_ok());
+        assert!(parse(&["--debug"]).is_err());
+    }
+
+    #[test]
+    fn maps_runs_to_exit_codes() {
+        let map = SourceMap::default();
+        assert_eq!(run_output(Ok(Some(-3)), &map), Ok("-3".to_owned()));
+        for result in [
+            Ok(None),
+            Err(RunError::CycleLimit(10)),
+            Err(RunError::Overflow { line: 3 }),
+            Err(RunError::StackOverflow { line: 3 }),
+        ] {
+            assert_eq!(run_output(result, &map).unwrap_err().0, EXIT_RUNTIME);
+        }
+    }
+}
@@ -0,0 +1,1176 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    fmt::Display,
+};
+
+use crate::{
+    diagnostic::{closest, Diagnostic},
+    instructions::{
+        Arithmetic, Array, Condition, DataRef, GenericFunction, Jumps, Label, Stack, VariableDef,
+        VariableSet, Wide,
+    },
+    source_map::Origin,
+    template::{Instruction, Template},
+};
+
+/// Compiles a single IR line into `template`.
+///
+/// The line is split into tokens first, see [`bind`]: inside of a function, its parameters,
+/// labels and variables are named `{function}@{name}`, `@` being reserved for such names. Labels
+/// are resolved against the lines given to [`State::declare`], which should see the whole
+/// program first.
+///
+/// The returned diagnostic points inside `s`; its file and line are left for the caller to set.
+pub fn compile<'a>(
+    s: Cow<'a, str>,
+    state: &mut State,
+    template: &mut Template,
+) -> Result<(), Box<Diagnostic>> {
+    let source = s.to_string();
+    let error =
+        |message: String, token: Option<usize>| Diagnostic::error(message).on_token(&source, token);
+    let written = s.split(' ').filter(|x| !x.is_empty()).collect::<Vec<_>>();
+    if let Some(i) = written.iter().skip(1).position(|x| x.contains('@')) {
+        return Err(Box::new(
+            error(format!("`{}` contains `@`", written[i + 1]), Some(i + 1)).with_note(
+                "note: `@` is reserved for the names of function locals, such as `f@name`",
+            ),
+        ));
+    }
+    match (&mut state.func_state, &written[..]) {
+        (Some(function), ["let" | "array" | "wide", name, ..]) => {
+            function.names.insert((*name).to_owned());
+        }
+        (Some(function), ["label", label, ..]) => {
+            function
+                .labels
+                .insert(label.trim_start_matches('\'').to_owned());
+        }
+        (None, ["label", label, ..]) => {
+            let labels = state.labels.entry(None).or_default();
+            labels.insert(label.trim_start_matches('\'').to_owned());
+        }
+        _ => (),
+    }
+    let tokens = match written.first() {
+        Some(&"func") | Some(&"rfunc") => written.iter().map(|x| Cow::Borrowed(*x)).collect(),
+        _ => written
+            .iter()
+            .enumerate()
+            .map(|(i, x)| match (i, written[0]) {
+                (0, _) | (1, "call") => Ok(Cow::Borrowed(*x)),
+                _ => bind(x, state).map_err(|e| Box::new(e.on_token(&source, Some(i)))),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    let mut iter = tokens.iter().map(|x| x.as_ref());
+    let fnname = iter
+        .next()
+        .ok_or_else(|| error("Can't find function name".to_owned(), None))?;
+    if fnname == "ret" {
+        let func = state
+            .func_state
+            .as_ref()
+            .ok_or_else(|| error("`ret` outside of a function".to_owned(), Some(0)))?;
+        Jumps::JumpFuncEnd(Cow::Borrowed(&func.name)).apply(template);
+        return Ok(());
+    }
+    if fnname == "call" {
+        let fnname = iter
+            .next()
+            .ok_or_else(|| error("`call` needs a function name".to_owned(), Some(0)))?;
+        if let Some(function) = state.cythan_funcs.get(fnname) {
+            let stack = function.stack;
+            let arguments = parse_values(iter, 2, &error)?;
+            check_declared(&arguments, 2, &written, state, &error)?;
+            if arguments.len() != function.arguments {
+                let diagnostic = error(
+                    format!(
+                        "`{}` takes {} argument(s) but {} were given",
+                        fnname,
+                        function.arguments,
+                        arguments.len()
+                    ),
+                    Some(1),
+                );
+                return Err(Box::new(match &function.origin {
+                    Some(e) => diagnostic.with_note(format!(
+                        "note: `{}` is defined at {} as `{}`",
+                        fnname, e, function.header
+                    )),
+                    None => diagnostic,
+                }));
+            }
+            let cycle = match &state.func_state {
+                Some(e) if !stack => state
+                    .call_path(fnname, &e.name)
+                    .map(|x| (x, e.name.clone())),
+                _ => None,
+            };
+            if let Some((path, caller)) = cycle {
+                let message = match path.split_first() {
+                    None => format!("`{}` calls itself", fnname),
+                    Some((_, through)) => format!(
+                        "`{}` calls itself through `{}`",
+                        fnname,
+                        [through, &[caller]].concat().join("`, `")
+                    ),
+                };
+                return Err(Box::new(
+                    error(message, Some(1))
+                        .with_note("note: the frame of a `func` is overwritten by any call to it")
+                        .with_note(format!(
+                            "help: declare it with `rfunc {}` to give it a call stack",
+                            fnname
+                        )),
+                ));
+            }
+            if stack {
+                let count = state.count();
+                Stack::Push(Cow::Borrowed(fnname), count).apply(template);
+            }
+            for (i, value) in arguments.iter().enumerate() {
+                match value {
+                    Value::RefNum(a) => VariableSet::FunctionInput(
+                        Cow::Borrowed(fnname),
+                        i as u8 + 1,
+                        DataRef::RefNum(*a),
+                    ),
+                    Value::Variable(a) => VariableSet::FunctionInput(
+                        Cow::Borrowed(fnname),
+                        i as u8 + 1,
+                        DataRef::Variable(a.clone()),
+                    ),
+                    Value::Num(_) | Value::Label(_) => {
+                        return Err(Box::new(
+                            error("invalid function argument".to_owned(), Some(i + 2))
+                                .with_note("help: expected a variable or `&num`"),
+                        ));
+                    }
+                }
+                .apply(template);
+            }
+            let count = state.count();
+            template.add_section(
+                "VAR_DEF",
+                Cow::Owned(format!("'#global_continue_{}:'continue_{}", count, count)),
+            );
+            template.add_code(Cow::Owned(format!(
+                "'#global_continue_{} '{}_cb",
+                count, fnname
+            )));
+            template.add_code(Cow::Owned(format!("jump('fnstart_{})", fnname)));
+            template.add_code(Cow::Owned(format!("'continue_{}:no_op", count)));
+            if stack {
+                let count = state.count();
+                Stack::Pop(Cow::Borrowed(fnname), count).apply(template);
+            }
+            return Ok(());
+        } else {
+            let diagnostic = error(format!("no function named `{}`", fnname), Some(1));
+            return Err(Box::new(
+                match closest(fnname, state.cythan_funcs.keys().map(|x| x.as_str())) {
+                    Some(e) => diagnostic.with_note(format!("help: did you mean `{}`?", e)),
+                    None => diagnostic
+                        .with_note(format!("note: the program has no `func {}` header", fnname)),
+                },
+            ));
+        }
+    }
+    if fnname == "func" || fnname == "rfunc" {
+        let name = iter
+            .next()
+            .ok_or_else(|| error(format!("`{}` needs a function name", fnname), Some(0)))?;
+        if let Some(e) = &state.func_state {
+            return Err(Box::new(
+                error(
+                    format!("`{} {}` inside of function `{}`", fnname, name, e.name),
+                    Some(0),
+                )
+                .with_note("help: close the previous function with `end_func`"),
+            ));
+        }
+        // A function defined twice is still entered, so that its body and `end_func` compile as
+        // they would in the first definition instead of failing outside of a function.
+        let defined = state
+            .define(format!("fnstart_{}", name), template.origin.as_ref())
+            .map_err(|e| {
+                Box::new(defined_twice(
+                    error(format!("`{}` is defined twice", name), Some(1)),
+                    e,
+                ))
+            });
+        let vec = iter.map(|x| x.to_owned()).collect::<Vec<_>>();
+        let stack = fnname == "rfunc" || state.stack_calls;
+        template.set_code_section(Cow::Borrowed("FUNCTION_DEF"));
+        if defined.is_ok() {
+            template.add_section("VAR_DEF", Cow::Owned(format!("'{}_cb:16", name)));
+            if stack {
+                Stack::Declare(Cow::Borrowed(name)).apply(template);
+            }
+            template.add_code(Cow::Owned(format!("'fnstart_{}:no_op\n", name)));
+            for (i, _) in vec.iter().enumerate() {
+                VariableDef::FunctionVariable(Cow::Borrowed(name), i as u8 + 1).apply(template);
+            }
+            state.cythan_funcs.insert(
+                name.to_owned(),
+                Function {
+                    arguments: vec.len(),
+                    stack,
+                    header: source.trim().to_owned(),
+                    origin: template.origin.clone(),
+                },
+            );
+        }
+        state.func_state = Some(FuncState {
+            name: name.to_owned(),
+            arguments: vec,
+            locals: stack.then(Vec::new),
+            names: state.locals.get(name).cloned().unwrap_or_default(),
+            labels: state
+                .labels
+                .get(&Some(name.to_owned()))
+                .cloned()
+                .unwrap_or_default(),
+        });
+        return defined;
+    }
+    if fnname == "end_func" {
+        let state1 = state
+            .func_state
+            .take()
+            .ok_or_else(|| error("`end_func` outside of a function".to_owned(), Some(0)))?;
+        Jumps::JumpFuncEnd(Cow::Borrowed(&state1.name)).apply(template);
+        if let Some(locals) = &state1.locals {
+            let cells = std::iter::once(format!("'{}_cb", state1.name))
+                .chain(
+                    (1..=state1.arguments.len()).map(|x| format!("'var_{}@in{}", state1.name, x)),
+                )
+                .chain(locals.iter().map(|x| format!("'var_{}", x)))
+                .collect();
+            Stack::Routines(Cow::Borrowed(&state1.name), cells).apply(template);
+        }
+        template.set_code_section(Cow::Borrowed("CODE"));
+        return Ok(());
+    }
+    if WIDE_INSTRUCTIONS.contains(&fnname) {
+        return compile_wide(fnname, iter, &written, state, template, &error);
+    }
+    if let Some((func, compiler)) = state.functions.get(fnname) {
+        let compiler = *compiler;
+        let arguments = parse_values(iter, 1, &error)?;
+        if func.len() != arguments.len() {
+            Err(Box::new(error(
+                format!(
+                    "`{}` takes {} argument(s) but {} were given",
+                    fnname,
+                    func.len(),
+                    arguments.len()
+                ),
+                Some(0),
+            )))
+        } else if let Some(i) = func
+            .iter()
+            .zip(arguments.iter())
+            .position(|(a, b)| !a.check(b))
+        {
+            Err(Box::new(
+                error(format!("invalid argument for `{}`", fnname), Some(i + 1))
+                    .with_note(format!("help: expected {}", func[i])),
+            ))
+        } else {
+            match fnname {
+                "let" | "array" => check_declared(&arguments[1..], 2, &written, state, &error)?,
+                _ => check_declared(&arguments, 1, &written, state, &error)?,
+            }
+            let defined = match (fnname, &arguments[..]) {
+                ("let", [Value::Variable(a), ..]) | ("array", [Value::Variable(a), ..]) => {
+                    Some(format!("var_{}", a))
+                }
+                ("label", [Value::Label(a)]) => Some(format!("label_{}", a)),
+                _ => None,
+            };
+            if let Some(defined) = defined {
+                state
+                    .define(defined, template.origin.as_ref())
+                    .map_err(|e| {
+                        defined_twice(
+                            error(format!("`{}` is defined twice", written[1]), Some(1)),
+                            e,
+                        )
+                    })?;
+            }
+            match (fnname, &arguments[..]) {
+                ("let", [Value::Variable(a), ..]) => state.record_locals(vec![a.to_string()]),
+                ("array", [Value::Variable(a), b]) => state.record_locals(
+                    (0..b.num().unwrap())
+                        .map(|x| format!("{}[{}]", a, x))
+                        .collect(),
+                ),
+                _ => (),
+            }
+            compiler(arguments, template);
+            Ok(())
+        }
+    } else {
+        let names = ["call", "func", "rfunc", "end_func", "ret"]
+            .iter()
+            .chain(WIDE_INSTRUCTIONS.iter())
+            .copied();
+        let diagnostic = error(format!("unknown instruction `{}`", fnname), Some(0));
+        Err(Box::new(
+            match closest(
+                fnname,
+                state.functions.keys().map(|x| x.as_str()).chain(names),
+            ) {
+                Some(e) => diagnostic.with_note(format!("help: did you mean `{}`?", e)),
+                None => diagnostic,
+            },
+        ))
+    }
+}
+
+/// The token `token` as named inside of the function being compiled: the input cell of the
+/// parameter it names if it is a `$name`, and `{function}@{name}` if it is one of the labels or
+/// variables of the function. Labels defined neither in the function nor outside of every
+/// function are an error.
+fn bind<'a>(token: &'a str, state: &State) -> Result<Cow<'a, str>, Box<Diagnostic>> {
+    let function = state.func_state.as_ref();
+    if let Some(label) = token.strip_prefix('\'') {
+        let top_level = state.labels.get(&None);
+        return match function {
+            Some(e) if e.labels.contains(label) => Ok(Cow::Owned(format!("'{}@{}", e.name, label))),
+            _ if top_level.is_some_and(|x| x.contains(label)) => Ok(Cow::Borrowed(token)),
+            _ => {
+                let diagnostic = Diagnostic::error(format!("undefined label `{}`", token));
+                let visible = function
+                    .map(|x| &x.labels)
+                    .into_iter()
+                    .chain(top_level)
+                    .flatten()
+                    .map(|x| x.as_str());
+                Err(Box::new(match closest(label, visible) {
+                    Some(e) => diagnostic.with_note(format!("help: did you mean `'{}`?", e)),
+                    None => diagnostic,
+                }))
+            }
+        };
+    }
+    let name = match (token.strip_prefix('$'), function) {
+        (Some(e), _) => e,
+        (None, Some(e)) if e.names.contains(token) => {
+            return Ok(Cow::Owned(format!("{}@{}", e.name, token)))
+        }
+        (None, _) => return Ok(Cow::Borrowed(token)),
+    };
+    let function = function.ok_or_else(|| {
+        Diagnostic::error(format!("parameter `{}` used outside of a function", token))
+            .with_note("note: parameters are declared by a `func` or `rfunc` header")
+    })?;
+    if let Some(i) = function.arguments.iter().position(|x| x == name) {
+        return Ok(Cow::Owned(format!("{}@in{}", function.name, i + 1)));
+    }
+    let diagnostic = Diagnostic::error(format!(
+        "`{}` has no parameter named `{}`",
+        function.name, token
+    ));
+    Err(Box::new(
+        match closest(name, function.arguments.iter().map(|x| x.as_str())) {
+            Some(e) => diagnostic.with_note(format!("help: did you mean `${}`?", e)),
+            None if function.arguments.is_empty() => {
+                diagnostic.with_note(format!("note: `{}` takes no parameters", function.name))
+            }
+            None => diagnostic.with_note(format!(
+                "note: the parameters of `{}` are {}",
+                function.name,
+                function
+                    .arguments
+                    .iter()
+                    .map(|x| format!("`${}`", x))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        },
+    ))
+}
+
+/// Error for the first variable of `arguments`, token number `first` of the line onwards, that is
+/// declared neither in the function being compiled nor outside of every function. `written` is
+/// the line as written, which the diagnostic names the variable from.
+fn check_declared(
+    arguments: &[Value],
+    first: usize,
+    written: &[&str],
+    state: &State,
+    error: &impl Fn(String, Option<usize>) -> Diagnostic,
+) -> Result<(), Box<Diagnostic>> {
+    for (i, argument) in arguments.iter().enumerate() {
+        match argument {
+            // Parameters and locals are bound to `{function}@{name}`.
+            Value::Variable(e) if !e.contains('@') && !state.globals.contains(e.as_ref()) => {
+                let name = written[first + i];
+                let diagnostic = error(format!("`{}` is never declared", name), Some(first + i));
+                let visible = state
+                    .func_state
+                    .iter()
+                    .flat_map(|x| &x.names)
+                    .chain(&state.globals)
+                    .map(|x| x.as_str());
+                return Err(Box::new(match closest(name, visible) {
+                    Some(e) => diagnostic.with_note(format!("help: did you mean `{}`?", e)),
+                    None => diagnostic.with_note(format!("help: declare it with `let {} 0`", name)),
+                }));
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Adds the note locating the first definition of a name defined twice to `diagnostic`.
+fn defined_twice(diagnostic: Diagnostic, first: Option<Origin>) -> Diagnostic {
+    match first {
+        Some(e) => diagnostic.with_note(format!("note: it is first defined at {}", e)),
+        None => diagnostic,
+    }
+}
+
+/// Parses the arguments of an instruction, the first one being token number `first` of the line.
+fn parse_values<'a>(
+    iter: impl Iterator<Item = &'a str>,
+    first: usize,
+    error: &impl Fn(String, Option<usize>) -> Diagnostic,
+) -> Result<Vec<Value<'a>>, Box<Diagnostic>> {
+    iter.enumerate()
+        .map(|(i, x)| {
+            Value::from_str(x).map_err(|e| Box::new(error(e.to_owned(), Some(first + i))))
+        })
+        .collect()
+}
+
+/// Instructions on wide variables, compiled by [`compile_wide`].
+const WIDE_INSTRUCTIONS: [&str; 9] = [
+    "wide", "wset", "widen", "swiden", "winc", "wdec", "wadd", "wsub", "wif_lt",
+];
+
+/// Compiles an instruction on wide variables, numbers of several nibbles declared with
+/// `wide NAME WIDTH VALUE`, checking that its operands have the same width.
+///
+/// `written` is the line as written, the instruction included, which diagnostics name operands
+/// from.
+fn compile_wide<'a>(
+    fnname: &str,
+    iter: impl Iterator<Item = &'a str>,
+    written: &[&str],
+    state: &mut State,
+    template: &mut Template,
+    error: &impl Fn(String, Option<usize>) -> Diagnostic,
+) -> Result<(), Box<Diagnostic>> {
+    let tokens = iter.collect::<Vec<_>>();
+    let arity = match fnname {
+        "winc" | "wdec" => 1,
+        "wset" | "widen" | "swiden" => 2,
+        _ => 3,
+    };
+    if tokens.len() != arity {
+        return Err(Box::new(error(
+            format!(
+                "`{}` takes {} argument(s) but {} were given",
+                fnname,
+                arity,
+                tokens.len()
+            ),
+            Some(0),
+        )));
+    }
+    if fnname == "wide" {
+        let name = tokens[0];
+        if !matches!(Value::from_str(name), Ok(Value::Variable(_))) {
+            return Err(Box::new(
+                error("invalid argument for `wide`".to_owned(), Some(1))
+                    .with_note("help: expected a variable"),
+            ));
+        }
+        let width = tokens[1]
+            .parse::<u8>()
+            .ok()
+            .filter(|x| (1..=4).contains(x))
+            .ok_or_else(|| {
+                error("invalid argument for `wide`".to_owned(), Some(2))
+                    .with_note("help: expected a width of 1 to 4 nibbles")
+            })?;
+        let max = (1u32 << (4 * width)) - 1;
+        let value = tokens[2]
+            .parse::<u32>()
+            .ok()
+            .filter(|x| *x <= max)
+            .ok_or_else(|| {
+                error("number out of range".to_owned(), Some(3))
+                    .with_note(format!("note: {} nibbles hold 0 to {}", width, max))
+            })?;
+        state
+            .define(format!("var_{}", name), template.origin.as_ref())
+            .map_err(|e| {
+                defined_twice(
+                    error(format!("`{}` is defined twice", written[1]), Some(1)),
+                    e,
+                )
+            })?;
+        state.widths.insert(name.to_owned(), width);
+        state.record_locals((0..width).map(|x| format!("{}[{}]", name, x)).collect());
+        Wide::Declare(Cow::Borrowed(name), width, value).apply(template);
+        return Ok(());
+    }
+    let wide = |i: usize| -> Result<(Cow<str>, u8), Box<Diagnostic>> {
+        match state.widths.get(tokens[i]) {
+            Some(e) => Ok((Cow::Borrowed(tokens[i]), *e)),
+            None => Err(Box::new(
+                error(
+                    format!("`{}` is not a wide variable", written[i + 1]),
+                    Some(i + 1),
+                )
+                .with_note(format!(
+                    "help: declare it with `wide {} 2 0`",
+                    written[i + 1]
+                )),
+            )),
+        }
+    };
+    let same = |operands: &[(Cow<str>, u8)]| -> Result<u8, Box<Diagnostic>> {
+        let width = operands[0].1;
+        match operands.iter().position(|x| x.1 != width) {
+            Some(i) => Err(Box::new(error(
+                format!(
+                    "`{}` has {} nibbles but `{}` has {}",
+                    written[1],
+                    width,
+                    written[i + 1],
+                    operands[i].1
+                ),
+                Some(i + 1),
+            ))),
+            None => Ok(width),
+        }
+    };
+    match fnname {
+        "wset" => {
+            let operands = [wide(0)?, wide(1)?];
+            let width = same(&operands)?;
+            let [a, b] = operands;
+            Wide::Set(a.0, b.0, width)
+        }
+        "widen" | "swiden" => {
+            let signed = fnname == "swiden";
+            let (a, width) = wide(0)?;
+            match state.widths.get(tokens[1]) {
+                Some(e) if *e > width => {
+                    return Err(Box::new(error(
+                        format!(
+                            "`{}` has {} nibbles, more than the {} of `{}`",
+                            written[2], e, width, written[1]
+                        ),
+                        Some(2),
+                    )))
+                }
+                Some(e) => Wide::Widen(
+                    a,
+                    DataRef::Variable(Cow::Borrowed(tokens[1])),
+                    *e,
+                    width,
+                    signed,
+                ),
+                None => {
+                    let b = Value::from_str(tokens[1])
+                        .ok()
+                        .and_then(|x| x.try_into().ok())
+                        .ok_or_else(|| {
+                            error(format!("invalid argument for `{}`", fnname), Some(2))
+                                .with_note("help: expected a variable or `&num`")
+                        })?;
+                    Wide::Widen(a, b, 1, width, signed)
+                }
+            }
+        }
+        "winc" => {
+            let (a, width) = wide(0)?;
+            Wide::Inc(a, width)
+        }
+        "wdec" => {
+            let (a, width) = wide(0)?;
+            Wide::Dec(a, width)
+        }
+        "wadd" | "wsub" => {
+            let operands = [wide(0)?, wide(1)?, wide(2)?];
+            let width = same(&operands)?;
+            let [a, b, c] = operands;
+            if fnname == "wadd" {
+                Wide::Add(a.0, b.0, c.0, width)
+            } else {
+                Wide::Sub(a.0, b.0, c.0, width)
+            }
+        }
+        _ => {
+            let operands = [wide(0)?, wide(1)?];
+            let width = same(&operands)?;
+            let label = tokens[2].strip_prefix('\'').ok_or_else(|| {
+                error("invalid argument for `wif_lt`".to_owned(), Some(3))
+                    .with_note("help: expected a `'label`")
+            })?;
+            let [a, b] = operands;
+            Wide::IfLt(a.0, b.0, Cow::Borrowed(label), width)
+        }
+    }
+    .apply(template);
+    Ok(())
+}
+
+type InstructionCompiler = fn(Vec<Value>, &mut Template);
+
+pub struct State {
+    functions: HashMap<String, (Vec<ValueType>, InstructionCompiler)>,
+    cythan_funcs: HashMap<String, Function>,
+    func_state: Option<FuncState>,
+    counter: usize,
+    /// Compile every `func` as an `rfunc`.
+    stack_calls: bool,
+    /// Number of nibbles of every wide variable.
+    widths: HashMap<String, u8>,
+    /// Variables declared inside of every function, found before compiling it.
+    locals: HashMap<String, HashSet<String>>,
+    /// Variables declared outside of every function, found before compiling them.
+    globals: HashSet<String>,
+    /// Labels defined outside of every function (`None`) and inside of every function, found
+    /// before compiling them.
+    labels: HashMap<Option<String>, HashSet<String>>,
+    /// Functions every function calls, found before compiling them.
+    calls: HashMap<String, HashSet<String>>,
+    /// Function whose lines are being declared.
+    declaring: Option<String>,
+    /// Line defining every variable (`var_{name}`), label (`label_{name}`) and function
+    /// (`fnstart_{name}`) compiled so far.
+    definitions: HashMap<String, Option<Origin>>,
+}
+
+impl State {
+    /// State compiling every function with a call stack, as if declared with `rfunc`.
+    pub fn with_stack_calls(mut self) -> Self {
+        self.stack_calls = true;
+        self
+    }
+
+    /// Records the function `line` defines if it is a `func` or `rfunc` header, so that calls
+    /// written before the definition can be compiled, and the variable it declares if it is in a
+    /// function, so that the function uses it from its first line. Labels are recorded with the
+    /// function defining them, so that only those are named after it.
+    pub(crate) fn declare(&mut self, line: &str, origin: Origin) {
+        let mut iter = line.split(' ').filter(|x| !x.is_empty());
+        let stack = match iter.next() {
+            Some("func") => self.stack_calls,
+            Some("rfunc") => true,
+            Some("end_func") => {
+                self.declaring = None;
+                return;
+            }
+            Some("let") | Some("array") | Some("wide") => {
+                match (&self.declaring, iter.next()) {
+                    (Some(function), Some(name)) => {
+                        self.locals
+                            .entry(function.clone())
+                            .or_default()
+                            .insert(name.to_owned());
+                    }
+                    (None, Some(name)) => {
+                        self.globals.insert(name.to_owned());
+                    }
+                    _ => (),
+                }
+                return;
+            }
+            Some("call") => {
+                if let (Some(function), Some(name)) = (&self.declaring, iter.next()) {
+                    self.calls
+                        .entry(function.clone())
+                        .or_default()
+                        .insert(name.to_owned());
+                }
+                return;
+            }
+            Some("label") => {
+                if let Some(label) = iter.next() {
+                    self.labels
+                        .entry(self.declaring.clone())
+                        .or_default()
+                        .insert(label.trim_start_matches('\'').to_owned());
+                }
+                return;
+            }
+            _ => return,
+        };
+        if let Some(name) = iter.next() {
+            self.declaring = Some(name.to_owned());
+            self.cythan_funcs
+                .entry(name.to_owned())
+                .or_insert_with(|| Function {
+                    arguments: iter.count(),
+                    stack,
+                    header: line.to_owned(),
+                    origin: Some(origin),
+                });
+        }
+    }
+
+    /// Functions a call to `from` goes through before calling `to`, `from` first, if it can,
+    /// nothing when `from` is `to`.
+    fn call_path(&self, from: &str, to: &str) -> Option<Vec<String>> {
+        let mut path = vec![from.to_owned()];
+        let mut visited = HashSet::new();
+        self.find_call_path(&mut path, to, &mut visited)
+            .then_some(path)
+    }
+
+    fn find_call_path(
+        &self,
+        path: &mut Vec<String>,
+        to: &str,
+        visited: &mut HashSet<String>,
+    ) -> bool {
+        let last = path[path.len() - 1].clone();
+        if last == to {
+            path.pop();
+            return true;
+        }
+        if !visited.insert(last.clone()) {
+            return false;
+        }
+        for callee in self.calls.get(&last).into_iter().flatten() {
+            path.push(callee.clone());
+            if self.find_call_path(path, to, visited) {
+                return true;
+            }
+            path.pop();
+        }
+        false
+    }
+
+    /// Records `cells` as locals of the function being compiled, if it has a call stack.
+    fn record_locals(&mut self, cells: Vec<String>) {
+        if let Some(FuncState {
+            locals: Some(e), ..
+        }) = &mut self.func_state
+        {
+            for cell in cells {
+                if !e.contains(&cell) {
+                    e.push(cell);
+                }
+            }
+        }
+    }
+
+    /// Records that `name` is defined at `origin`, or gives the origin of its first definition
+    /// if it is already defined.
+    fn define(&mut self, name: String, origin: Option<&Origin>) -> Result<(), Option<Origin>> {
+        match self.definitions.get(&name) {
+            Some(e) => Err(e.clone()),
+            None => {
+                self.definitions.insert(name, origin.cloned());
+                Ok(())
+            }
+        }
+    }
+
+    fn count(&mut self) -> usize {
+        self.counter += 1;
+        self.counter
+    }
+}
+
+/// A function of the program, as given by its header.
+struct Function {
+    arguments: usize,
+    /// Whether it uses the stack calling convention.
+    stack: bool,
+    /// The `func` or `rfunc` line defining it.
+    header: String,
+    origin: Option<Origin>,
+}
+
+struct FuncState {
+    name: String,
+    arguments: Vec<String>,
+    /// Variables and array elements declared in the function, saved with its frame, if it has a
+    /// call stack.
+    locals: Option<Vec<String>>,
+    /// Variables declared in the function, named `{function}@{name}` in it.
+    names: HashSet<String>,
+    /// Labels defined in the function, named `{function}@{name}` in it.
+    labels: HashSet<String>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            counter: 0,
+            stack_calls: false,
+            widths: HashMap::new(),
+            locals: HashMap::new(),
+            globals: HashSet::new(),
+            labels: HashMap::new(),
+            calls: HashMap::new(),
+            declaring: None,
+            definitions: HashMap::new(),
+            cythan_funcs: HashMap::new(),
+            func_state: None,
+            functions: {
+                let mut map: HashMap<String, (Vec<ValueType>, InstructionCompiler)> =
+                    HashMap::new();
+                map.insert(
+                    "label".to_owned(),
+                    (vec![ValueType::Label], |a, b| {
+                        Label::Label(a[0].label().unwrap().clone()).apply(b);
+                    }),
+                );
+                map.insert(
+                    "let".to_owned(),
+                    (vec![ValueType::Variable, ValueType::Num], |a, b| {
+                        VariableDef::NumberVariable(
+                            a[0].var().unwrap().clone(),
+                            a[1].num().unwrap(),
+                        )
+                        .apply(b);
+                    }),
+                );
+                map.insert(
+                    "if_0".to_owned(),
+                    (vec![ValueType::Variable, ValueType::Label], |a, b| {
+                        Condition::If0(a[0].var().unwrap().clone(), a[1].label().unwrap().clone())
+                            .apply(b);
+                    }),
+                );
+                map.insert(
+                    "if_lt".to_owned(),
+                    (
+                        vec![
+                            ValueType::Or(vec![ValueType::Variable, ValueType::RefNum]),
+                            ValueType::Or(vec![ValueType::Variable, ValueType::RefNum]),
+                            ValueType::Label,
+                        ],
+                        |a, b| {
+                            Condition::IfLt(
+                                a[0].clone().try_into().unwrap(),
+                                a[1].clone().try_into().unwrap(),
+                                a[2].label().unwrap().clone(),
+                            )
+                            .apply(b);
+                        },
+                    ),
+                );
+                map.insert(
+                    "set".to_owned(),
+                    (
+                        vec![
+                            ValueType::Variable,
+                            ValueType::Or(vec![ValueType::Variable, ValueType::RefNum]),
+                        ],
+                        |a, b| {
+                            match &a[1] {
+                                Value::Variable(e) => {
+                                    VariableSet::Variable(a[0].var().unwrap().clone(), e.clone())
+                                }
+                                Value::RefNum(e) => {
+                                    VariableSet::Number(a[0].var().unwrap().clone(), *e)
+                                }
+                                _ => unreachable!(),
+                            }
+                            .apply(b);
+                        },
+                    ),
+                );
+                map.insert(
+                    "exit".to_owned(),
+                    (
+                        vec![ValueType::Or(vec![ValueType::Variable, ValueType::RefNum])],
+                        |a, b| {
+                            GenericFunction::Exit(a[0].clone().try_into().unwrap()).apply(b);
+                        },
+                    ),
+                );
+                map.insert(
+                    "exit_signed".to_owned(),
+                    (
+                        vec![ValueType::Or(vec![ValueType::Variable, ValueType::RefNum])],
+                        |a, b| {
+                            GenericFunction::ExitSigned(a[0].clone().try_into().unwrap()).apply(b);
+                        },
+                    ),
+                );
+                map.insert(
+                    "inc".to_owned(),
+                    (vec![ValueType::Variable], |a, b| {
+                        GenericFunction::Inc(a[0].var().unwrap().clone()).apply(b);
+                    }),
+                );
+                let steps: [(&str, InstructionCompiler); 4] = [
+                    ("checked_inc", |a, b| {
+                        GenericFunction::CheckedInc(a[0].var().unwrap().clone()).apply(b)
+                    }),
+                    ("checked_dec", |a, b| {
+                        GenericFunction::CheckedDec(a[0].var().unwrap().clone()).apply(b)
+                    }),
+                    ("saturating_inc", |a, b| {
+                        GenericFunction::SaturatingInc(a[0].var().unwrap().clone()).apply(b)
+                    }),
+                    ("saturating_dec", |a, b| {
+                        GenericFunction::SaturatingDec(a[0].var().unwrap().clone()).apply(b)
+                    }),
+                ];
+                for (name, compiler) in steps {
+                    map.insert(name.to_owned(), (vec![ValueType::Variable], compiler));
+                }
+                map.insert(
+                    "no_op".to_owned(),
+                    (vec![], |_, b| GenericFunction::NoOp.apply(b)),
+                );
+                map.insert(
+                    "dec".to_owned(),
+                    (vec![ValueType::Variable], |a, b| {
+                        GenericFunction::Dec(a[0].var().unwrap().clone()).apply(b);
+                    }),
+                );
+                map.insert(
+                    "jump".to_owned(),
+                    (vec![ValueType::Label], |a, b| {
+                        Jumps::JumpLabel(a[0].label().unwrap().clone()).apply(b);
+                    }),
+                );
+                map.insert(
+                    "jump_var".to_owned(),
+                    (vec![ValueType::Variable], |a, b| {
+                        Jumps::JumpVariable(a[0].var().unwrap().clone()).apply(b);
+                    }),
+                );
+                map.insert(
+                    "set_lbl".to_owned(),
+                    (vec![ValueType::Variable, ValueType::Label], |a, b| {
+                        VariableSet::Label(
+                            a[0].var().unwrap().clone(),
+                            a[1].label().unwrap().clone(),
+                        )
+                        .apply(b)
+                    }),
+                );
+                map.insert(
+                    "array".to_owned(),
+                    (vec![ValueType::Variable, ValueType::Length], |a, b| {
+                        Array::Declare(a[0].var().unwrap().clone(), a[1].num().unwrap()).apply(b);
+                    }),
+                );
+                map.insert(
+                    "load".to_owned(),
+                    (
+                        vec![
+                            ValueType::Variable,
+                            ValueType::Variable,
+                            ValueType::Or(vec![ValueType::Variable, ValueType::RefNum]),
+                        ],
+                        |a, b| {
+                            Array::Load(
+                                a[0].var().unwrap().clone(),
+                                a[1].var().unwrap().clone(),
+                                a[2].clone().try_into().unwrap(),
+                            )
+                            .apply(b);
+                        },
+                    ),
+                );
+                map.insert(
+                    "store".to_owned(),
+                    (
+                        vec![
+                            ValueType::Variable,
+                            ValueType::Or(vec![ValueType::Variable, ValueType::RefNum]),
+                            ValueType::Or(vec![ValueType::Variable, ValueType::RefNum]),
+                        ],
+                        |a, b| {
+                            Array::Store(
+                                a[0].var().unwrap().clone(),
+                                a[1].clone().try_into().unwrap(),
+                                a[2].clone().try_into().unwrap(),
+                            )
+                            .apply(b);
+                        },
+                    ),
+                );
+                let arithmetic: [(&str, InstructionCompiler); 11] = [
+                    ("add", |a, b| {
+                        let (c, d, e) = operands(a);
+                        Arithmetic::Add(c, d, e).apply(b)
+                    }),
+                    ("sub", |a, b| {
+                        let (c, d, e) = operands(a);
+                        Arithmetic::Sub(c, d, e).apply(b)
+                    }),
+                    ("mul", |a, b| {
+                        let (c, d, e) = operands(a);
+                        Arithmetic::Mul(c, d, e).apply(b)
+                    }),
+                    ("div", |a, b| {
+                        let (c, d, e) = operands(a);
+                        Arithmetic::Div(c, d, e).apply(b)
+                    }),
+                    ("mod", |a, b| {
+                        let (c, d, e) = operands(a);
+                        Arithmetic::Mod(c, d, e).apply(b)
+                    }),
+                    ("checked_add", |a, b| {
+                        let (c, d, e) = operands(a);
+                        Arithmetic::CheckedAdd(c, d, e).apply(b)
+                    }),
+                    ("checked_sub", |a, b| {
+                        let (c, d, e) = operands(a);
+                        Arithmetic::CheckedSub(c, d, e).apply(b)
+                    }),
+                    ("checked_mul", |a, b| {
+                        let (c, d, e) = operands(a);
+                        Arithmetic::CheckedMul(c, d, e).apply(b)
+                    }),
+                    ("saturating_add", |a, b| {
+                        let (c, d, e) = operands(a);
+                        Arithmetic::SaturatingAdd(c, d, e).apply(b)
+                    }),
+                    ("saturating_sub", |a, b| {
+                        let (c, d, e) = operands(a);
+                        Arithmetic::SaturatingSub(c, d, e).apply(b)
+                    }),
+                    ("saturating_mul", |a, b| {
+                        let (c, d, e) = operands(a);
+                        Arithmetic::SaturatingMul(c, d, e).apply(b)
+                    }),
+                ];
+                for (name, compiler) in arithmetic {
+                    map.insert(
+                        name.to_owned(),
+                        (
+                            vec![
+                                ValueType::Variable,
+                                ValueType::Or(vec![ValueType::Variable, ValueType::RefNum]),
+                                ValueType::Or(vec![ValueType::Variable, ValueType::RefNum]),
+                            ],
+                            compiler,
+                        ),
+                    );
+                }
+                map
+            },
+        }
+    }
+}
+
+/// Destination and operands of an arithmetic instruction.
+fn operands(mut values: Vec<Value>) -> (Cow<str>, DataRef, DataRef) {
+    let b = values.pop().unwrap().try_into().unwrap();
+    let a = values.pop().unwrap().try_into().unwrap();
+    let destination = match values.pop() {
+        Some(Value::Variable(e)) => e,
+        _ => unreachable!(),
+    };
+    (destination, a, b)
+}
+
+#[derive(Clone)]
+pub(crate) enum Value<'a> {
+    RefNum(u8),
+    Variable(Cow<'a, str>),
+    Num(u8),
+    Label(Cow<'a, str>),
+}
+
+impl<'a> Value<'a> {
+    fn var(&'a self) -> Option<&'a Cow<'a, str>> {
+        match &self {
+            Self::Variable(e) => Some(e),
+            _ => None,
+        }
+    }
+    fn label(&'a self) -> Option<&'a Cow<'a, str>> {
+        match &self {
+            Self::Label(e) => Some(e),
+            _ => None,
+        }
+    }
+    fn num(&self) -> Option<u8> {
+        Some(match self {
+            Self::Num(0) => 16,
+            Self::Num(a) => *a,
+            _ => return None,
+        })
+    }
+    fn from_str(s: &'a str) -> Result<Self, &'static str> {
+        Ok(if let Some(label) = s.strip_prefix('\'') {
+            Self::Label(Cow::Borrowed(label))
+        } else if let Some(number) = s.strip_prefix('&') {
+            if let Ok(e) = number.parse::<u8>() {
+                if e > 15 {
+                    return Err("number out of range, expected 0 to 15");
+                }
+                Self::RefNum(e)
+            } else {
+                return Err("No variable ref allowed");
+            }
+        } else {
+            if let Ok(e) = s.parse::<u8>() {
+                // 16 is only a length, which `ValueType` checks.
+                if e > 16 {
+                    return Err("number out of range, expected 0 to 15");
+                }
+                Self::Num(e)
+            } else {
+                Self::Variable(Cow::Borrowed(s))
+            }
+        })
+    }
+}
+
+enum ValueType {
+    Or(Vec<ValueType>),
+    RefNum,
+    Variable,
+    Num,
+    /// Number of elements of an array.
+    Length,
+    Label,
+}
+
+impl Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueType::Or(e) => write!(
+                f,
+                "{}",
+                e.iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            ),
+            ValueType::RefNum => write!(f, "`&num`"),
+            ValueType::Variable => write!(f, "a variable"),
+            ValueType::Num => write!(f, "a number from 0 to 15"),
+            ValueType::Length => write!(f, "a length, arrays have 1 to 16 elements"),
+            ValueType::Label => write!(f, "a `'label`"),
+        }
+    }
+}
+
+impl ValueType {
+    fn check(&self, value: &Value) -> bool {
+        match self {
+            ValueType::Or(e) => e.iter().any(|x| x.check(value)),
+            ValueType::RefNum => matches!(value, Value::RefNum(_)),
+            ValueType::Variable => matches!(value, Value::Variable(_)),
+            ValueType::Num => matches!(value, Value::Num(e) if *e < 16),
+            ValueType::Length => matches!(value, Value::Num(e) if *e != 0),
+            ValueType::Label => matches!(value, Value::Label(_)),
+        }
+    }
+}
@@ -0,0 +1,590 @@
+//! Step debugger running an assembled program on a [`Machine`].
+//!
+//! Locations come from the [`SourceMap`]s of the program: the address of the instruction pointer
+//! gives the template line it was assembled from (see [`Image::lines`]), which every map turns
+//! into a line of the file it describes, such as the IR or the high-level source.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{BufRead, Write},
+};
+
+use crate::{
+    assembler::Image,
+    closest,
+    vm::{as_number, Machine, RunError},
+    Origin, SourceMap,
+};
+
+/// Why the debugger gave control back.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The requested steps are done.
+    Paused,
+    /// The program reached a breakpoint.
+    Breakpoint(Origin),
+    /// A watched cell changed.
+    Watch {
+        name: String,
+        old: usize,
+        new: usize,
+    },
+    /// The program reached `stop`, with its exit value.
+    Stopped(Option<i8>),
+    /// The program stopped in the trap of a checked operation, at the given origin.
+    Overflow(Option<Origin>),
+    /// The program stopped on a call deeper than a call stack, at the given origin.
+    StackOverflow(Option<Origin>),
+    /// The machine failed to run the cycle.
+    Error(RunError),
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::Paused => Ok(()),
+            Event::Breakpoint(e) => write!(f, "breakpoint at {}", e),
+            Event::Watch { name, old, new } => {
+                write!(f, "{} changed: {} -> {}", name, value(*old), value(*new))
+            }
+            Event::Stopped(Some(e)) => write!(f, "the program exited with {}", e),
+            Event::Stopped(None) => write!(f, "the program stopped without an exit value"),
+            Event::Overflow(Some(e)) => write!(f, "arithmetic overflow at {}", e),
+            Event::Overflow(None) => write!(f, "arithmetic overflow"),
+            Event::StackOverflow(Some(e)) => write!(f, "stack overflow at {}", e),
+            Event::StackOverflow(None) => write!(f, "stack overflow"),
+            Event::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// A cell shown as the number it stands for, or as a raw value when it holds no number.
+fn value(cell: usize) -> String {
+    match as_number(cell) {
+        Some(e) => e.to_string(),
+        None => format!("{} (raw)", cell),
+    }
+}
+
+struct Watch {
+    name: String,
+    address: usize,
+    value: usize,
+}
+
+pub struct Debugger {
+    pub machine: Machine,
+    image: Image,
+    /// Maps from template lines to the files they come from, the last one being the file shown
+    /// and stepped through by `next`.
+    maps: Vec<SourceMap>,
+    /// Content of the files the maps point to, to show the current line.
+    sources: HashMap<String, String>,
+    breakpoints: Vec<Origin>,
+    watches: Vec<Watch>,
+    /// Cycles after which `continue` and `next` give up.
+    pub max_cycles: u64,
+    stopped: bool,
+}
+
+impl Debugger {
+    pub fn new(image: Image, maps: Vec<SourceMap>, max_cycles: u64) -> Self {
+        Self {
+            machine: Machine::new(&image),
+            image,
+            maps,
+            sources: HashMap::new(),
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            max_cycles,
+            stopped: false,
+        }
+    }
+
+    /// Registers the content of `file` so that locations in it are shown with their code.
+    pub fn add_source(&mut self, file: &str, source: &str) {
+        self.sources.insert(file.to_owned(), source.to_owned());
+    }
+
+    /// Origin of the instruction about to run, in every map.
+    pub fn location(&self) -> Vec<Option<&Origin>> {
+        let pointer = self.machine.get(0);
+        let line = self.image.lines.get(pointer).copied().unwrap_or(0);
+        self.maps.iter().map(|x| x.origin(line)).collect()
+    }
+
+    /// Origin of the instruction about to run in the last map.
+    fn current(&self) -> Option<Origin> {
+        self.location().last().copied().flatten().cloned()
+    }
+
+    /// Text of the line `origin` points to, if its file was registered.
+    fn source_line(&self, origin: &Origin) -> Option<&str> {
+        self.sources
+            .get(&origin.file)?
+            .lines()
+            .nth(origin.line - 1)
+            .map(|x| x.trim())
+    }
+
+    /// Address of a label (with or without its `'`), of the variable `var_<name>` or of a local
+    /// `var_<function>.<name>` or `var_<function>@<name>`, or an address written as a number.
+    pub fn resolve(&self, name: &str) -> Option<usize> {
+        let name = name.trim_start_matches('\'');
+        let locals = [format!(".{}", name), format!("@{}", name)];
+        name.parse()
+            .ok()
+            .or_else(|| self.image.label(name))
+            .or_else(|| self.image.label(&format!("var_{}", name)))
+            .or_else(|| {
+                self.variables()
+                    .into_iter()
+                    .find(|(label, _)| locals.iter().any(|x| label.ends_with(x.as_str())))
+                    .map(|x| x.1)
+            })
+    }
+
+    /// Name of the global label at `address`, preferring the labels of variables.
+    fn label_at(&self, address: usize) -> Option<&str> {
+        self.image
+            .sorted_labels()
+            .into_iter()
+            .filter(|x| x.1 == address)
+            .map(|x| x.0)
+            .max_by_key(|x| x.starts_with("var_"))
+    }
+
+    /// Variables of the program (`'var_x`, `'f@in1` arguments, `'f_cb` return addresses).
+    pub fn variables(&self) -> Vec<(&str, usize)> {
+        self.image
+            .sorted_labels()
+            .into_iter()
+            .filter(|(name, _)| name.starts_with("var_") || name.ends_with("_cb"))
+            .collect()
+    }
+
+    /// `name = value` line for the cell at `address`, return addresses being shown with the
+    /// label they point to.
+    pub fn describe(&self, name: &str, address: usize) -> String {
+        let cell = self.machine.get(address);
+        if name.ends_with("_cb") {
+            match self.label_at(cell) {
+                Some(e) => format!("{} = {} ('{})", name, cell, e),
+                None => format!("{} = {}", name, cell),
+            }
+        } else {
+            format!("{} = {}", name, value(cell))
+        }
+    }
+
+    /// Adds a breakpoint, hit whenever the program enters code coming from `origin`.
+    pub fn add_breakpoint(&mut self, origin: Origin) {
+        if !self.breakpoints.contains(&origin) {
+            self.breakpoints.push(origin);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, origin: &Origin) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|x| x != origin);
+        count != self.breakpoints.len()
+    }
+
+    /// Pauses the program whenever the cell at `address` changes.
+    pub fn watch(&mut self, name: &str, address: usize) {
+        self.watches.push(Watch {
+            name: name.to_owned(),
+            address,
+            value: self.machine.get(address),
+        });
+    }
+
+    /// Runs one cycle, returning the event that should pause the program if any.
+    fn cycle(&mut self) -> Option<Event> {
+        if self.stopped {
+            return Some(self.stop());
+        }
+        match self.machine.step() {
+            Ok(true) => {
+                self.stopped = true;
+                return Some(self.stop());
+            }
+            Ok(false) => (),
+            Err(e) => return Some(Event::Error(e)),
+        }
+        for watch in &mut self.watches {
+            let new = self.machine.get(watch.address);
+            if new != watch.value {
+                let old = std::mem::replace(&mut watch.value, new);
+                return Some(Event::Watch {
+                    name: watch.name.clone(),
+                    old,
+                    new,
+                });
+            }
+        }
+        None
+    }
+
+    /// Event of the program having stopped.
+    fn stop(&self) -> Event {
+        if self.machine.overflowed() {
+            Event::Overflow(self.current())
+        } else if self.machine.stack_overflowed() {
+            Event::StackOverflow(self.current())
+        } else {
+            Event::Stopped(self.machine.exit_value())
+        }
+    }
+
+    /// Runs `count` cycles, stopping at the cycle limit like the other ways of running.
+    pub fn step(&mut self, count: u64) -> Event {
+        let limit = self.machine.cycles.saturating_add(self.max_cycles);
+        for _ in 0..count {
+            if self.machine.cycles >= limit {
+                return Event::Error(RunError::CycleLimit(self.machine.cycles));
+            }
+            if let Some(e) = self.cycle() {
+                return e;
+            }
+        }
+        Event::Paused
+    }
+
+    /// Runs until the program reaches another line of the last map.
+    pub fn next_line(&mut self) -> Event {
+        let start = self.current();
+        self.run_until(|debugger| {
+            let current = debugger.current();
+            current.is_some() && current != start
+        })
+    }
+
+    /// Runs until a breakpoint is reached.
+    pub fn resume(&mut self) -> Event {
+        self.run_until(|_| false)
+    }
+
+    /// Runs until `done` holds or a breakpoint is entered.
+    fn run_until(&mut self, done: impl Fn(&Self) -> bool) -> Event {
+        let limit = self.machine.cycles.saturating_add(self.max_cycles);
+        while self.machine.cycles < limit {
+            let before: Vec<Option<Origin>> =
+                self.location().into_iter().map(|x| x.cloned()).collect();
+            if let Some(e) = self.cycle() {
+                return e;
+            }
+            let after = self.location();
+            let entered = self.breakpoints.iter().find(|breakpoint| {
+                after
+                    .iter()
+                    .zip(&before)
+                    .any(|(a, b)| *a == Some(*breakpoint) && b.as_ref() != Some(*breakpoint))
+            });
+            if let Some(e) = entered {
+                return Event::Breakpoint(e.clone());
+            }
+            if done(self) {
+                return Event::Paused;
+            }
+        }
+        Event::Error(RunError::CycleLimit(self.machine.cycles))
+    }
+
+    /// `where` output: the cycle count, the instruction pointer and the current line of every map.
+    pub fn where_am_i(&self) -> String {
+        let mut out = format!(
+            "cycle {}, instruction pointer at {}",
+            self.machine.cycles,
+            self.machine.get(0)
+        );
+        for origin in self.location().into_iter().flatten() {
+            out.push_str(&format!("\n  {}", origin));
+            if let Some(e) = self.source_line(origin) {
+                out.push_str(&format!(" | {}", e));
+            }
+        }
+        out
+    }
+}
+
+const HELP: &str = "Commands:
+  s, step [N]          Run N cycles (1 by default)
+  n, next              Run until the next source line
+  c, continue          Run until a breakpoint, a watch or the end of the program
+  b, break LINE        Break on entering LINE, written `file:line` or `line` for the source
+  d, delete LINE       Remove the breakpoint on LINE
+  p, print NAME        Show a variable (`a`, `var_a` or `'var_a`), label or address
+  v, vars              Show every variable
+  w, watch NAME        Pause whenever the cell of NAME changes
+  x ADDRESS [N]        Show N cells from ADDRESS (8 by default)
+  where                Show the current location
+  h, help              Show this message
+  q, quit              Leave the debugger";
+
+/// Interactive front end reading commands from `input`, `file` being the default file of
+/// breakpoints written as a plain line number.
+pub fn repl(
+    debugger: &mut Debugger,
+    file: &str,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> std::io::Result<()> {
+    writeln!(output, "{}\n{}", HELP, debugger.where_am_i())?;
+    loop {
+        write!(output, "(cythan) ")?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(e) => e,
+            None => continue,
+        };
+        let argument = words.next();
+        let event = match command {
+            "q" | "quit" => return Ok(()),
+            "h" | "help" => {
+                writeln!(output, "{}", HELP)?;
+                continue;
+            }
+            "s" | "step" => match argument.map(|x| x.parse()).unwrap_or(Ok(1)) {
+                Ok(e) => debugger.step(e),
+                Err(_) => {
+                    writeln!(output, "error: expected a number of cycles")?;
+                    continue;
+                }
+            },
+            "n" | "next" => debugger.next_line(),
+            "c" | "continue" => debugger.resume(),
+            "where" => {
+                writeln!(output, "{}", debugger.where_am_i())?;
+                continue;
+            }
+            "b" | "break" | "d" | "delete" => {
+                let origin = match argument.and_then(|x| parse_origin(x, file)) {
+                    Some(e) => e,
+                    None => {
+                        writeln!(output, "error: expected a line as `file:line` or `line`")?;
+                        continue;
+                    }
+                };
+                if command.starts_with('b') {
+                    writeln!(output, "breakpoint set at {}", origin)?;
+                    debugger.add_breakpoint(origin);
+                } else if debugger.remove_breakpoint(&origin) {
+                    writeln!(output, "breakpoint at {} removed", origin)?;
+                } else {
+                    writeln!(output, "error: no breakpoint at {}", origin)?;
+                }
+                continue;
+            }
+            "v" | "vars" => {
+                for (name, address) in debugger.variables() {
+                    writeln!(output, "{}", debugger.describe(name, address))?;
+                }
+                continue;
+            }
+            "p" | "print" | "w" | "watch" | "x" => {
+                let name = argument.unwrap_or("");
+                let address = match debugger.resolve(name) {
+                    Some(e) => e,
+                    None => {
+                        let names = debugger.variables();
+                        let suggestion = closest(name, names.iter().map(|x| x.0));
+                        writeln!(
+                            output,
+                            "error: unknown variable or label `{}`{}",
+                            name,
+                            suggestion
+                                .map(|x| format!(", did you mean `{}`?", x))
+                                .unwrap_or_default()
+                        )?;
+                        continue;
+                    }
+                };
+                let name = name.trim_start_matches('\'');
+                match command {
+                    "x" => {
+                        let count = match words.next().map(|x| x.parse::<usize>()) {
+                            None => 8,
+                            Some(Ok(e)) => e,
+                            Some(Err(_)) => {
+                                writeln!(output, "error: expected a number of cells")?;
+                                continue;
+                            }
+                        };
+                        let size = debugger.machine.memory.len();
+                        let end = match address.checked_add(count) {
+                            Some(_) if address >= size => {
+                                writeln!(
+                                    output,
+                                    "error: address {} is past the end of memory ({} cells)",
+                                    address, size
+                                )?;
+                                continue;
+                            }
+                            Some(e) => e.min(size),
+                            None => {
+                                writeln!(
+                                    output,
+                                    "error: {} cells from address {} are out of range",
+                                    count, address
+                                )?;
+                                continue;
+                            }
+                        };
+                        let cells: Vec<String> = debugger.machine.memory[address..end]
+                            .iter()
+                            .map(|x| x.to_string())
+                            .collect();
+                        writeln!(output, "{:>6} | {}", address, cells.join(" "))?;
+                    }
+                    "w" | "watch" => {
+                        debugger.watch(name, address);
+                        writeln!(output, "watching {}", debugger.describe(name, address))?;
+                    }
+                    _ => writeln!(output, "{}", debugger.describe(name, address))?,
+                }
+                continue;
+            }
+            e => {
+                writeln!(output, "error: unknown command `{}`, try `help`", e)?;
+                continue;
+            }
+        };
+        if event != Event::Paused {
+            writeln!(output, "{}", event)?;
+        }
+        writeln!(output, "{}", debugger.where_am_i())?;
+    }
+}
+
+/// Parses `file:line`, or `line` in `file`.
+fn parse_origin(text: &str, file: &str) -> Option<Origin> {
+    match text.rsplit_once(':') {
+        Some((a, b)) => Some(Origin::new(a, b.parse().ok()?)),
+        None => Some(Origin::new(file, text.parse().ok()?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        assembler::assemble, compile_lines, declare_lines, State, Template, DEFAULT_TEMPLATE,
+    };
+
+    const IR: &str = "let a 2
+let b 5
+inc a
+inc b
+inc b
+exit a";
+
+    /// What the debugger answers to every line of `commands` when debugging `IR`.
+    fn session(commands: &str) -> Vec<String> {
+        limited_session(commands, 100_000)
+    }
+
+    /// Replies to `commands` of a debugger giving up after `max_cycles` cycles.
+    fn limited_session(commands: &str, max_cycles: u64) -> Vec<String> {
+        let template = DEFAULT_TEMPLATE.replace('\r', "");
+        let mut template = Template::new(&template);
+        let mut state = State::default();
+        declare_lines("in.ct", IR.lines(), &mut state);
+        compile_lines("in.ct", IR.lines(), &mut state, &mut template).unwrap();
+        let image = assemble("<template output>", &template.build()).unwrap();
+        let mut debugger = Debugger::new(image, vec![template.build_source_map()], max_cycles);
+        debugger.add_source("in.ct", IR);
+        let mut output = Vec::new();
+        repl(&mut debugger, "in.ct", Cursor::new(commands), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let mut replies: Vec<String> = output
+            .split("(cythan) ")
+            .skip(1)
+            .map(|x| x.trim_end().to_owned())
+            .collect();
+        replies.pop();
+        replies
+    }
+
+    #[test]
+    fn breaks_on_ir_lines() {
+        let replies = session("b 4\nc\np a\nd 4\nd 4\nc");
+        assert_eq!(replies[0], "breakpoint set at in.ct:4");
+        assert!(replies[1].starts_with("breakpoint at in.ct:4\n"));
+        assert!(replies[1].ends_with("in.ct:4 | inc b"));
+        assert_eq!(replies[2], "a = 3");
+        assert_eq!(replies[3], "breakpoint at in.ct:4 removed");
+        assert_eq!(replies[4], "error: no breakpoint at in.ct:4");
+        assert!(replies[5].starts_with("the program exited with 3\n"));
+    }
+
+    #[test]
+    fn steps_cycles_and_lines() {
+        let replies = session("s\ns 2\nn\nn\nwhere");
+        assert!(replies[0].starts_with("cycle 1,"));
+        assert!(replies[1].starts_with("cycle 3,"));
+        assert!(replies[2].ends_with("in.ct:4 | inc b"));
+        assert!(replies[3].ends_with("in.ct:5 | inc b"));
+        assert_eq!(replies[4], replies[3]);
+    }
+
+    #[test]
+    fn stops_steps_at_the_cycle_limit() {
+        let replies = limited_session("s 1000\ns 3", 2);
+        assert!(replies[0].starts_with("the program did not stop after 2 cycles\ncycle 2,"));
+        assert!(replies[1].starts_with("the program did not stop after 4 cycles\ncycle 4,"));
+    }
+
+    #[test]
+    fn prints_variables() {
+        let replies = session("p a\np var_b\np 'var_b\nb 5\nc\nv");
+        assert_eq!(replies[0], "a = 2");
+        assert_eq!(replies[1], "var_b = 5");
+        assert_eq!(replies[2], "var_b = 5");
+        assert_eq!(replies[5], "var_a = 3\nvar_b = 6");
+    }
+
+    #[test]
+    fn pauses_on_watched_changes() {
+        let replies = session("w b\nc\nc\nc");
+        assert_eq!(replies[0], "watching b = 5");
+        assert!(replies[1].starts_with("b changed: 5 -> 6\n"));
+        assert!(replies[2].starts_with("b changed: 6 -> 7\n"));
+        assert!(replies[3].starts_with("the program exited with 3\n"));
+    }
+
+    #[test]
+    fn shows_memory_ranges() {
+        let replies = session("x a 1\nx a 0\nx 1 18446744073709551615\nx 99999999\nx 1 many");
+        assert!(replies[0].ends_with(" | 2"));
+        assert!(replies[1].ends_with(" |"));
+        assert_eq!(
+            replies[2],
+            "error: 18446744073709551615 cells from address 1 are out of range"
+        );
+        assert!(replies[3].starts_with("error: address 99999999 is past the end of memory"));
+        assert_eq!(replies[4], "error: expected a number of cells");
+    }
+
+    #[test]
+    fn reports_bad_input() {
+        let replies = session("foo\np c\nb x\ns many\n\nq\nwhere");
+        assert_eq!(replies[0], "error: unknown command `foo`, try `help`");
+        assert_eq!(replies[1], "error: unknown variable or label `c`");
+        assert_eq!(
+            replies[2],
+            "error: expected a line as `file:line` or `line`"
+        );
+        assert_eq!(replies[3], "error: expected a number of cycles");
+        assert_eq!(replies[4], "");
+        assert_eq!(replies.len(), 5);
+    }
+}
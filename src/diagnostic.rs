@@ -0,0 +1,184 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    /// Extra information about another diagnostic.
+    Note,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// A message attached to a place in a source file, rendered with a snippet of that line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Name of the file as shown to the user.
+    pub file: String,
+    /// One-based line of the error.
+    pub line: usize,
+    /// One-based column of `token` in `source_line`.
+    pub column: usize,
+    /// The offending token, empty when the whole line is at fault.
+    pub token: String,
+    /// Text of the line the diagnostic points to.
+    pub source_line: String,
+    /// Extra `help: ...` or `note: ...` lines shown under the snippet.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            file: String::new(),
+            line: 1,
+            column: 1,
+            token: String::new(),
+            source_line: String::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    pub fn note(message: impl Into<String>) -> Self {
+        Self::new(Severity::Note, message)
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Points the diagnostic at `line`, or at its `index`-th space separated token if it exists.
+    pub fn on_token(mut self, line: &str, index: Option<usize>) -> Self {
+        self.source_line = line.to_owned();
+        self.column = 1;
+        self.token = String::new();
+        if let Some((column, token)) = index.and_then(|x| tokens(line).nth(x)) {
+            self.column = column + 1;
+            self.token = token.to_owned();
+        }
+        self
+    }
+
+    /// Points the diagnostic at the byte range `start..end` of `source`, the content of `file`.
+    pub fn in_source(mut self, file: &str, source: &str, start: usize, end: usize) -> Self {
+        let start = start.min(source.len());
+        let line_start = source[..start].rfind('\n').map(|x| x + 1).unwrap_or(0);
+        let line_end = source[start..]
+            .find('\n')
+            .map(|x| x + start)
+            .unwrap_or_else(|| source.len());
+        self.file = file.to_owned();
+        self.line = source[..start].matches('\n').count() + 1;
+        self.column = source[line_start..start].chars().count() + 1;
+        self.source_line = source[line_start..line_end].to_owned();
+        self.token = source[start..end.clamp(start, line_end)].to_owned();
+        self
+    }
+
+    /// Places the diagnostic in `file` at the one-based `line`.
+    pub fn in_file(mut self, file: &str, line: usize) -> Self {
+        self.file = file.to_owned();
+        self.line = line;
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(f, "{}: {}", self.severity, self.message)?;
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter, self.file, self.line, self.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+        let underline = if self.token.is_empty() {
+            let code = self.source_line.trim();
+            let indent = self.source_line.len() - self.source_line.trim_start().len();
+            format!(
+                "{}{}",
+                " ".repeat(indent),
+                "^".repeat(code.chars().count().max(1))
+            )
+        } else {
+            format!(
+                "{}{}",
+                " ".repeat(self.column - 1),
+                "^".repeat(self.token.chars().count())
+            )
+        };
+        write!(f, "{} | {}", gutter, underline.trim_end_matches(' '))?;
+        for note in &self.notes {
+            write!(f, "\n{} = {}", gutter, note)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Finds the candidate closest to `name`, if one is close enough to be a likely typo.
+pub fn closest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .map(|x| (edit_distance(name, x), x))
+        .filter(|(distance, x)| *distance <= (x.len().max(name.len()) / 3).max(1))
+        .min()
+        .map(|(_, x)| x)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                previous.min(current).min(row[j]) + 1
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+/// Space separated tokens of `line` with their byte offset.
+pub(crate) fn tokens(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    line.split(' ')
+        .scan(0, |offset, token| {
+            let start = *offset;
+            *offset += token.len() + 1;
+            Some((start, token))
+        })
+        .filter(|(_, token)| !token.is_empty())
+}
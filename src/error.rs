@@ -0,0 +1,45 @@
+use std::fmt::Display;
+
+use crate::{Diagnostic, SourceMap};
+
+/// Error returned when an IR program fails to compile, with every diagnostic found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Error {
+    /// Number of diagnostics that are errors rather than warnings or notes.
+    pub fn error_count(&self) -> usize {
+        self.diagnostics.iter().filter(|x| x.is_error()).count()
+    }
+
+    /// Notes where the line of generated code every diagnostic points to comes from, in each
+    /// of `maps`, such as the IR and the source an assembly error goes back to.
+    pub fn locate(mut self, maps: &[&SourceMap]) -> Self {
+        for diagnostic in &mut self.diagnostics {
+            let line = diagnostic.line;
+            for origin in maps.iter().filter_map(|x| x.origin(line)) {
+                diagnostic
+                    .notes
+                    .push(format!("note: generated from {}", origin));
+            }
+        }
+        self
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, diagnostic) in self.diagnostics.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,46 @@
+use std::borrow::Cow;
+
+use crate::{
+    instructions::DataRef,
+    template::{Instruction, Template},
+};
+
+/// Operations storing their result in the variable given first, wrapping around 16 unless they
+/// are checked or saturating.
+pub enum Arithmetic<'a> {
+    Add(Cow<'a, str>, DataRef<'a>, DataRef<'a>),
+    Sub(Cow<'a, str>, DataRef<'a>, DataRef<'a>),
+    Mul(Cow<'a, str>, DataRef<'a>, DataRef<'a>),
+    /// Operations stopping the program with `'#overflow` set when the result is out of 0 to 15,
+    /// the instruction pointer being left in the operation.
+    CheckedAdd(Cow<'a, str>, DataRef<'a>, DataRef<'a>),
+    CheckedSub(Cow<'a, str>, DataRef<'a>, DataRef<'a>),
+    CheckedMul(Cow<'a, str>, DataRef<'a>, DataRef<'a>),
+    /// Operations giving 15 or 0 when the result is out of 0 to 15.
+    SaturatingAdd(Cow<'a, str>, DataRef<'a>, DataRef<'a>),
+    SaturatingSub(Cow<'a, str>, DataRef<'a>, DataRef<'a>),
+    SaturatingMul(Cow<'a, str>, DataRef<'a>, DataRef<'a>),
+    /// Division rounding down, dividing by 0 gives 0.
+    Div(Cow<'a, str>, DataRef<'a>, DataRef<'a>),
+    /// Remainder of the division, the remainder of a division by 0 being the dividend.
+    Mod(Cow<'a, str>, DataRef<'a>, DataRef<'a>),
+}
+
+impl Instruction for Arithmetic<'_> {
+    fn apply(&self, template: &mut Template) {
+        let (name, a, b, c) = match self {
+            Arithmetic::Add(a, b, c) => ("add", a, b, c),
+            Arithmetic::Sub(a, b, c) => ("sub", a, b, c),
+            Arithmetic::Mul(a, b, c) => ("mul", a, b, c),
+            Arithmetic::CheckedAdd(a, b, c) => ("checked_add", a, b, c),
+            Arithmetic::CheckedSub(a, b, c) => ("checked_sub", a, b, c),
+            Arithmetic::CheckedMul(a, b, c) => ("checked_mul", a, b, c),
+            Arithmetic::SaturatingAdd(a, b, c) => ("saturating_add", a, b, c),
+            Arithmetic::SaturatingSub(a, b, c) => ("saturating_sub", a, b, c),
+            Arithmetic::SaturatingMul(a, b, c) => ("saturating_mul", a, b, c),
+            Arithmetic::Div(a, b, c) => ("div", a, b, c),
+            Arithmetic::Mod(a, b, c) => ("mod", a, b, c),
+        };
+        template.add_code(Cow::Owned(format!("{}('var_{} {} {})", name, a, b, c)));
+    }
+}
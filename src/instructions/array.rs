@@ -0,0 +1,51 @@
+use std::borrow::Cow;
+
+use crate::{
+    instructions::DataRef,
+    template::{Instruction, Template},
+};
+
+/// Arrays of numbers, indexed through a table of the addresses of their elements.
+///
+/// The table has an entry for every number, an index past the end wrapping around the size of
+/// the array, so that any index reads or writes one of its elements.
+pub enum Array<'a> {
+    /// Elements and address table of an array of the given size, from 1 to 16.
+    Declare(Cow<'a, str>, u8),
+    /// Copies the element of the array at the index into the variable given first.
+    Load(Cow<'a, str>, Cow<'a, str>, DataRef<'a>),
+    /// Copies the value given last into the element of the array at the index.
+    Store(Cow<'a, str>, DataRef<'a>, DataRef<'a>),
+}
+
+impl Instruction for Array<'_> {
+    fn apply(&self, template: &mut Template) {
+        match self {
+            Array::Declare(a, b) => {
+                for i in 0..*b {
+                    template.add_section("VAR_DEF", Cow::Owned(format!("'var_{}[{}]:16", a, i)));
+                }
+                for i in 0..16 {
+                    template.add_section(
+                        "VAR_DEF",
+                        Cow::Owned(format!("'#array_{}_{}:'var_{}[{}]", a, i, a, i % b)),
+                    );
+                }
+            }
+            Array::Load(a, b, c) => {
+                template.add_code(Cow::Owned(format!("load({} 'var_{} {})", c, a, table(b))))
+            }
+            Array::Store(a, b, c) => {
+                template.add_code(Cow::Owned(format!("store({} {} {})", b, c, table(a))))
+            }
+        }
+    }
+}
+
+/// Cells holding the address of the element of `array` at every index.
+fn table(array: &str) -> String {
+    (0..16)
+        .map(|x| format!("'#array_{}_{}", array, x))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
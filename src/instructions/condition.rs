@@ -1,9 +1,14 @@
 use std::borrow::Cow;
 
-use crate::template::{Instruction, Template};
+use crate::{
+    instructions::DataRef,
+    template::{Instruction, Template},
+};
 
 pub enum Condition<'a> {
     If0(Cow<'a, str>, Cow<'a, str>),
+    /// Jumps to the label if the first value is lower than the second.
+    IfLt(DataRef<'a>, DataRef<'a>, Cow<'a, str>),
 }
 
 impl Instruction for Condition<'_> {
@@ -12,6 +17,9 @@ impl Instruction for Condition<'_> {
             Self::If0(a, b) => {
                 template.add_code(Cow::Owned(format!("if_0('var_{} 'label_{})", a, b)));
             }
+            Self::IfLt(a, b, c) => {
+                template.add_code(Cow::Owned(format!("if_lt({} {} 'label_{})", a, b, c)));
+            }
         }
     }
 }
@@ -1,15 +1,25 @@
 use std::{borrow::Cow, convert::TryFrom, fmt::Display};
 
 use crate::{
+    compiler::Value,
     template::{Instruction, Template},
     utils::number_to_hex,
-    Value,
 };
 
 pub enum GenericFunction<'a> {
     Exit(DataRef<'a>),
+    /// Exits with a value read as a signed number, from -8 to 7.
+    ExitSigned(DataRef<'a>),
     Inc(Cow<'a, str>),
     Dec(Cow<'a, str>),
+    /// Increments the variable, stopping the program if it is 15.
+    CheckedInc(Cow<'a, str>),
+    /// Decrements the variable, stopping the program if it is 0.
+    CheckedDec(Cow<'a, str>),
+    /// Increments the variable unless it is 15.
+    SaturatingInc(Cow<'a, str>),
+    /// Decrements the variable unless it is 0.
+    SaturatingDec(Cow<'a, str>),
     NoOp,
 }
 
@@ -43,8 +53,23 @@ impl Instruction for GenericFunction<'_> {
     fn apply(&self, template: &mut Template) {
         match self {
             GenericFunction::Exit(a) => template.add_code(Cow::Owned(format!("exit({})", a))),
+            GenericFunction::ExitSigned(a) => {
+                template.add_code(Cow::Owned(format!("exit_signed({})", a)))
+            }
             GenericFunction::Inc(a) => template.add_code(Cow::Owned(format!("inc('var_{})", a))),
             GenericFunction::Dec(a) => template.add_code(Cow::Owned(format!("dec('var_{})", a))),
+            GenericFunction::CheckedInc(a) => {
+                template.add_code(Cow::Owned(format!("checked_inc('var_{})", a)))
+            }
+            GenericFunction::CheckedDec(a) => {
+                template.add_code(Cow::Owned(format!("checked_dec('var_{})", a)))
+            }
+            GenericFunction::SaturatingInc(a) => {
+                template.add_code(Cow::Owned(format!("saturating_inc('var_{})", a)))
+            }
+            GenericFunction::SaturatingDec(a) => {
+                template.add_code(Cow::Owned(format!("saturating_dec('var_{})", a)))
+            }
             GenericFunction::NoOp => template.add_code(Cow::Borrowed("no_op")),
         }
     }
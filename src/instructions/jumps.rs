@@ -2,6 +2,7 @@ use std::borrow::Cow;
 
 use crate::template::Instruction;
 
+#[allow(clippy::enum_variant_names)]
 pub enum Jumps<'a> {
     JumpLabel(Cow<'a, str>),
     JumpVariable(Cow<'a, str>),
@@ -1,11 +1,19 @@
+mod arithmetic;
+mod array;
 mod condition;
 mod generic_functions;
 mod jumps;
 mod label;
+mod stack;
 mod variables;
+mod wide;
 
+pub use arithmetic::*;
+pub use array::*;
 pub use condition::*;
 pub use generic_functions::*;
 pub use jumps::*;
 pub use label::*;
+pub use stack::*;
 pub use variables::*;
+pub use wide::*;
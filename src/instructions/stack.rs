@@ -0,0 +1,133 @@
+use std::borrow::Cow;
+
+use crate::template::{Instruction, Template};
+
+/// Frames a stack function keeps, the stack pointer being a number.
+///
+/// A call made while they are all in use, the function being 16 calls deep already, stops the
+/// program with a stack overflow instead of overwriting the first one.
+pub const STACK_DEPTH: usize = 16;
+
+/// Calling convention of functions declared with `rfunc`: before every call, the caller pushes
+/// the frame of the callee (its return address, arguments and locals) on a stack of its own and
+/// pops it back once the call returned, so that a call never loses the frame of a running one.
+///
+/// The stack holds [`STACK_DEPTH`] frames, `'{function}_full` being set once the last one is
+/// pushed.
+pub enum Stack<'a> {
+    /// Stack pointer, full flag and routine return cells of a function.
+    Declare(Cow<'a, str>),
+    /// Saves the frame of a function, stopping the program if its stack is full, the number
+    /// naming the continuation label.
+    Push(Cow<'a, str>, usize),
+    /// Restores the frame saved by the last push.
+    Pop(Cow<'a, str>, usize),
+    /// Push and pop routines of a function and the frame slots they use, for the given cells.
+    Routines(Cow<'a, str>, Vec<String>),
+}
+
+impl Instruction for Stack<'_> {
+    fn apply(&self, template: &mut Template) {
+        match self {
+            Stack::Declare(a) => {
+                template.add_section("VAR_DEF", Cow::Owned(format!("'{}_sp:16", a)));
+                template.add_section("VAR_DEF", Cow::Owned(format!("'{}_full:16", a)));
+                template.add_section("VAR_DEF", Cow::Owned(format!("'{}_push_cb:0", a)));
+                template.add_section("VAR_DEF", Cow::Owned(format!("'{}_pop_cb:0", a)));
+            }
+            Stack::Push(a, b) => {
+                template.add_code(Cow::Owned(format!("check_stack('{}_full)", a)));
+                call_routine(template, &format!("{}_push", a), *b)
+            }
+            Stack::Pop(a, b) => call_routine(template, &format!("{}_pop", a), *b),
+            Stack::Routines(a, b) => {
+                for frame in 0..STACK_DEPTH {
+                    for (i, _) in b.iter().enumerate() {
+                        template.add_section(
+                            "VAR_DEF",
+                            Cow::Owned(format!("'{}_frame{}_{}:0", a, frame, i)),
+                        );
+                    }
+                }
+                routine(template, a, "push", b, |cell, slot| {
+                    format!("{} {}", cell, slot)
+                });
+                routine(template, a, "pop", b, |cell, slot| {
+                    format!("{} {}", slot, cell)
+                });
+            }
+        }
+    }
+}
+
+/// Runs the routine `name` and comes back.
+fn call_routine(template: &mut Template, name: &str, count: usize) {
+    template.add_section(
+        "VAR_DEF",
+        Cow::Owned(format!("'#global_continue_{}:'continue_{}", count, count)),
+    );
+    template.add_code(Cow::Owned(format!(
+        "'#global_continue_{} '{}_cb",
+        count, name
+    )));
+    template.add_code(Cow::Owned(format!("jump('{})", name)));
+    template.add_code(Cow::Owned(format!("'continue_{}:no_op", count)));
+}
+
+/// Writes the routine `'{function}_{kind}` copying every cell of `cells` from or to the frame
+/// slot the stack pointer points to, `copy` giving the code copying a cell and its slot.
+///
+/// A push saves to the current slot then increments the pointer, setting the full flag when it
+/// wraps around to the first slot; a pop decrements it first and clears the flag.
+fn routine(
+    template: &mut Template,
+    function: &str,
+    kind: &str,
+    cells: &[String],
+    copy: impl Fn(&str, &str) -> String,
+) {
+    let name = format!("{}_{}", function, kind);
+    let targets = (0..STACK_DEPTH)
+        .map(|x| format!("'{}{}", name, x))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if kind == "push" {
+        template.add_code(Cow::Owned(format!(
+            "'{}:dispatch('{}_sp {})",
+            name, function, targets
+        )));
+    } else {
+        template.add_code(Cow::Owned(format!("'{}:dec('{}_sp)", name, function)));
+        template.add_code(Cow::Owned(format!(
+            "dispatch('{}_sp {})",
+            function, targets
+        )));
+    }
+    for frame in 0..STACK_DEPTH {
+        let copies = cells
+            .iter()
+            .enumerate()
+            .map(|(i, x)| copy(x, &format!("'{}_frame{}_{}", function, frame, i)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        template.add_code(Cow::Owned(format!(
+            "'{}{}:{} jump('{}_done)",
+            name, frame, copies, name
+        )));
+    }
+    if kind == "push" {
+        template.add_code(Cow::Owned(format!("'{}_done:inc('{}_sp)", name, function)));
+        template.add_code(Cow::Owned(format!(
+            "if_0('{}_sp '{}_wrapped) jump('{}_return)",
+            function, name, name
+        )));
+        template.add_code(Cow::Owned(format!(
+            "'{}_wrapped:'#1 '{}_full",
+            name, function
+        )));
+        template.add_code(Cow::Owned(format!("'{}_return:no_op", name)));
+    } else {
+        template.add_code(Cow::Owned(format!("'{}_done:'#0 '{}_full", name, function)));
+    }
+    template.add_code(Cow::Owned(format!("'{}_cb ~+3 ~+2 0 earasable", name)));
+}
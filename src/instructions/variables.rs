@@ -18,7 +18,7 @@ impl Instruction for VariableDef<'_> {
                 template.add_section("VAR_DEF", Cow::Owned(format!("'var_{}:{}", a, b)))
             }
             VariableDef::FunctionVariable(a, b) => {
-                template.add_section("VAR_DEF", Cow::Owned(format!("'var_{}_in{}:0", a, b)))
+                template.add_section("VAR_DEF", Cow::Owned(format!("'var_{}@in{}:0", a, b)))
             }
         }
     }
@@ -42,23 +42,19 @@ impl Instruction for VariableSet<'_> {
                 // CHANGED FROM format!("{} 'var_{}", b, a)
             }
             VariableSet::Label(a, b) => {
-                if template
-                    .section_contains("VAR_DEF", &format!("'#var_label_{}:'label_{}\n", b, b))
-                {
-                    template.add_section(
-                        "VAR_DEF",
-                        Cow::Owned(format!("'#var_label_{}:'label_{}\n", b, b)),
-                    );
+                let definition = format!("'#var_label_{}:'label_{}", b, b);
+                if !template.section_contains("VAR_DEF", &definition) {
+                    template.add_section("VAR_DEF", Cow::Owned(definition));
                 }
-                template.add_code(Cow::Owned(format!("'#var_label_{} 'var_{}\n", b, a,)));
+                template.add_code(Cow::Owned(format!("'#var_label_{} 'var_{}", b, a)));
             }
             VariableSet::FunctionInput(a, b, c) => match c {
                 DataRef::Variable(c) => {
-                    template.add_code(Cow::Owned(format!("'var_{} 'var_{}_in{}\n", c, a, b)));
+                    template.add_code(Cow::Owned(format!("'var_{} 'var_{}@in{}\n", c, a, b)));
                 }
                 DataRef::RefNum(c) => {
                     template.add_code(Cow::Owned(format!(
-                        "'#{} 'var_{}_in{}\n",
+                        "'#{} 'var_{}@in{}\n",
                         number_to_hex(*c),
                         a,
                         b
@@ -0,0 +1,111 @@
+use std::borrow::Cow;
+
+use crate::{
+    instructions::DataRef,
+    template::{Instruction, Template},
+};
+
+/// Numbers of several nibbles, stored as the cells `'var_{name}[k]`, the lowest nibble first.
+///
+/// Every operation goes from the lowest nibble to the highest one, passing the carry (or the
+/// borrow) in `'#carry`. Operands of an operation all have the width given last.
+pub enum Wide<'a> {
+    /// Nibbles of a variable of the given width, holding the number.
+    Declare(Cow<'a, str>, u8, u32),
+    /// Copies the second variable to the first one.
+    Set(Cow<'a, str>, Cow<'a, str>, u8),
+    /// Copies a value of the width given first to the first variable, of the width given last,
+    /// its other nibbles being set to 0, or to the sign of the value if the flag is set. A value
+    /// of width 1 is a nibble variable or number.
+    Widen(Cow<'a, str>, DataRef<'a>, u8, u8, bool),
+    Inc(Cow<'a, str>, u8),
+    Dec(Cow<'a, str>, u8),
+    Add(Cow<'a, str>, Cow<'a, str>, Cow<'a, str>, u8),
+    Sub(Cow<'a, str>, Cow<'a, str>, Cow<'a, str>, u8),
+    /// Jumps to the label if the first variable is lower than the second.
+    IfLt(Cow<'a, str>, Cow<'a, str>, Cow<'a, str>, u8),
+}
+
+impl Instruction for Wide<'_> {
+    fn apply(&self, template: &mut Template) {
+        match self {
+            Wide::Declare(a, b, c) => {
+                for i in 0..*b {
+                    let nibble = (c >> (4 * i)) & 15;
+                    template.add_section(
+                        "VAR_DEF",
+                        Cow::Owned(format!(
+                            "'var_{}[{}]:{}",
+                            a,
+                            i,
+                            if nibble == 0 { 16 } else { nibble }
+                        )),
+                    );
+                }
+            }
+            Wide::Set(a, b, c) => {
+                for i in 0..*c {
+                    template.add_code(Cow::Owned(format!("'var_{}[{}] 'var_{}[{}]", b, i, a, i)));
+                }
+            }
+            Wide::Widen(a, b, c, d, e) => {
+                let top = if *c == 1 {
+                    b.to_string()
+                } else {
+                    format!("{}[{}]", b, c - 1)
+                };
+                let fill = if *e {
+                    template.add_code(Cow::Owned(format!("sign({} '#temp_2)", top)));
+                    "'#temp_2"
+                } else {
+                    "'#0"
+                };
+                if *c == 1 {
+                    template.add_code(Cow::Owned(format!("{} 'var_{}[0]", b, a)));
+                } else {
+                    for i in 0..*c {
+                        template.add_code(Cow::Owned(format!("{}[{}] 'var_{}[{}]", b, i, a, i)));
+                    }
+                }
+                for i in *c..*d {
+                    template.add_code(Cow::Owned(format!("{} 'var_{}[{}]", fill, a, i)));
+                }
+            }
+            Wide::Inc(a, b) | Wide::Dec(a, b) => {
+                let name = match self {
+                    Wide::Inc(..) => "inc_carry",
+                    _ => "dec_borrow",
+                };
+                template.add_code(Cow::Borrowed("'#1 '#carry"));
+                for i in 0..*b {
+                    template.add_code(Cow::Owned(format!("{}('var_{}[{}] '#carry)", name, a, i)));
+                }
+            }
+            Wide::Add(a, b, c, d) | Wide::Sub(a, b, c, d) => {
+                let name = match self {
+                    Wide::Add(..) => "adc",
+                    _ => "sbb",
+                };
+                template.add_code(Cow::Borrowed("'#0 '#carry"));
+                for i in 0..*d {
+                    template.add_code(Cow::Owned(format!(
+                        "{}('var_{}[{}] 'var_{}[{}] 'var_{}[{}] '#carry)",
+                        name, a, i, b, i, c, i
+                    )));
+                }
+            }
+            Wide::IfLt(a, b, c, d) => {
+                // `a < b` exactly when `a - b` borrows from beyond the highest nibble.
+                template.add_code(Cow::Borrowed("'#0 '#carry"));
+                for i in 0..*d {
+                    template.add_code(Cow::Owned(format!(
+                        "sbb('#temp_1 'var_{}[{}] 'var_{}[{}] '#carry)",
+                        a, i, b, i
+                    )));
+                }
+                template.add_code(Cow::Borrowed("dec('#carry)"));
+                template.add_code(Cow::Owned(format!("if_0('#carry 'label_{})", c)));
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+};
+
+/// Process exit code when the program failed to compile.
+pub const EXIT_COMPILE_ERROR: i32 = 1;
+/// Process exit code for an invalid command line.
+pub const EXIT_USAGE: i32 = 2;
+/// Process exit code when an input or output file could not be read or written.
+pub const EXIT_IO: i32 = 3;
+/// Process exit code when a program run with `--run` did not exit with a value: it did not stop
+/// within the cycle limit, stopped in a trap or without an exit value.
+pub const EXIT_RUNTIME: i32 = 4;
+
+/// Reads a file, or stdin when `path` is `-`.
+pub fn read_input(path: &str) -> Result<String, String> {
+    let mut out = String::new();
+    if path == "-" {
+        std::io::stdin()
+            .read_to_string(&mut out)
+            .map_err(|e| io_error("<stdin>", e))?;
+    } else {
+        out = std::fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+    }
+    Ok(out.replace('\r', ""))
+}
+
+/// Writes a file, or stdout when `path` is `-`.
+pub fn write_output(path: &str, content: &str) -> Result<(), String> {
+    if path == "-" {
+        let stdout = std::io::stdout();
+        let mut lock = stdout.lock();
+        lock.write_all(content.as_bytes())
+            .and_then(|_| lock.write_all(b"\n"))
+            .map_err(|e| io_error("<stdout>", e))
+    } else {
+        std::fs::write(path, content).map_err(|e| io_error(path, e))
+    }
+}
+
+fn io_error(path: &str, error: impl Display) -> String {
+    format!("{}: {}", path, error)
+}
+
+/// Name used for `path` in messages, `<stdin>` for `-`.
+pub fn display_path(path: &str) -> &str {
+    if path == "-" {
+        "<stdin>"
+    } else {
+        path
+    }
+}
@@ -0,0 +1,76 @@
+//! Compiler from the Cythan IR (`.ct` files such as `code.ct`) to Cythan template code.
+
+use std::borrow::Cow;
+
+pub mod assembler;
+pub mod cli;
+mod compiler;
+pub mod debugger;
+mod diagnostic;
+mod error;
+mod instructions;
+pub mod io;
+mod source_map;
+mod template;
+mod utils;
+pub mod vm;
+
+pub use compiler::{compile, State};
+pub use diagnostic::{closest, Diagnostic, Severity};
+pub use error::Error;
+pub use source_map::{Origin, SourceMap};
+pub use template::{Instruction, Template, TemplatePiece, DEFAULT_TEMPLATE};
+
+/// Compiles a whole IR program into the template given as `template` and returns the built code.
+pub fn compile_str(ir: &str, template: &str) -> Result<String, Error> {
+    let template = template.replace('\r', "");
+    let mut template = Template::new(&template);
+    let mut state = State::default();
+    declare_lines("<input>", ir.lines(), &mut state);
+    compile_lines("<input>", ir.lines(), &mut state, &mut template)?;
+    Ok(template.build())
+}
+
+/// Declares the functions defined in `lines`, the content of `file`, so that the program can call
+/// them before their definition. Errors in their headers are left for [`compile_lines`].
+pub fn declare_lines<'a>(file: &str, lines: impl IntoIterator<Item = &'a str>, state: &mut State) {
+    for (line, raw) in lines.into_iter().enumerate() {
+        state.declare(raw.trim(), Origin::new(file, line + 1));
+    }
+}
+
+/// Compiles every IR line of `lines` into `template`, skipping blank lines and `#` comments.
+///
+/// The code generated for every line is recorded as coming from that line of `file`, see
+/// [`Template::build_source_map`].
+///
+/// Compilation goes on after an error so that every diagnostic of the file is reported, `file`
+/// being the name they refer to. Calling it several times with the same `state` compiles
+/// consecutive files as one program, whose files should all go through [`declare_lines`] first.
+pub fn compile_lines<'a>(
+    file: &str,
+    lines: impl IntoIterator<Item = &'a str>,
+    state: &mut State,
+    template: &mut Template,
+) -> Result<(), Error> {
+    let mut diagnostics = Vec::new();
+    for (line, raw) in lines.into_iter().enumerate() {
+        let code = raw.trim();
+        if code.starts_with('#') || code.is_empty() {
+            continue;
+        }
+        template.origin = Some(Origin::new(file, line + 1));
+        if let Err(e) = compile(Cow::Borrowed(code), state, template) {
+            let mut e = *e;
+            e.column += raw.len() - raw.trim_start().len();
+            e.source_line = raw.to_owned();
+            diagnostics.push(e.in_file(file, line + 1));
+        }
+    }
+    template.origin = None;
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(Error { diagnostics })
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt::Display;
+
+/// Line of a source file some generated code comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub file: String,
+    /// One-based line in `file`.
+    pub line: usize,
+}
+
+impl Origin {
+    pub fn new(file: &str, line: usize) -> Self {
+        Self {
+            file: file.to_owned(),
+            line,
+        }
+    }
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Origin of every line of a generated file, `None` for lines copied from the template.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    pub lines: Vec<Option<Origin>>,
+}
+
+impl SourceMap {
+    /// Origin of the one-based `line` of the generated file.
+    pub fn origin(&self, line: usize) -> Option<&Origin> {
+        self.lines.get(line.checked_sub(1)?)?.as_ref()
+    }
+
+    /// Maps every origin through `f`, used to chain the map of a file to the map of its source.
+    pub fn map(&self, f: impl Fn(&Origin) -> Option<Origin>) -> SourceMap {
+        SourceMap {
+            lines: self.lines.iter().map(|x| x.as_ref().and_then(&f)).collect(),
+        }
+    }
+
+    /// Appends a `# from file:line` comment to the lines of `code` starting code from a new origin.
+    pub fn annotate(&self, code: &str) -> String {
+        let mut previous = None;
+        code.split('\n')
+            .enumerate()
+            .map(|(i, line)| {
+                let origin = self.origin(i + 1);
+                let annotated = match origin {
+                    Some(e) if previous != origin && !line.trim().is_empty() => {
+                        format!("{} # from {}", line, e)
+                    }
+                    _ => line.to_owned(),
+                };
+                previous = origin;
+                annotated
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// One `<generated line> <file>:<line>` entry per line having an origin.
+impl Display for SourceMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut first = true;
+        for (i, origin) in self.lines.iter().enumerate() {
+            if let Some(origin) = origin {
+                if !first {
+                    writeln!(f)?;
+                }
+                first = false;
+                write!(f, "{} {}", i + 1, origin)?;
+            }
+        }
+        Ok(())
+    }
+}
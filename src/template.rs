@@ -1,8 +1,17 @@
 use std::borrow::Cow;
 
+use crate::source_map::{Origin, SourceMap};
+
+/// The template shipped with the compiler (`template.ct`).
+pub const DEFAULT_TEMPLATE: &str = include_str!("../template.ct");
+
 pub struct Template<'a> {
     pub pieces: Vec<TemplatePiece<'a>>,
     pub current_code_section: Cow<'a, str>,
+    /// Origin recorded for the code added from now on.
+    pub origin: Option<Origin>,
+    /// Origin of every string of every piece, `origins[i]` belonging to `pieces[i]`.
+    origins: Vec<Vec<Option<Origin>>>,
 }
 
 impl<'a> Template<'a> {
@@ -10,21 +19,20 @@ impl<'a> Template<'a> {
         let mut pieces = Vec::new();
         let mut current_template = Vec::new();
         for i in string.lines() {
-            if i.starts_with("# header ") {
+            if let Some(header) = i.strip_prefix("# header ") {
                 pieces.push(TemplatePiece::Section(current_template));
                 current_template = vec![];
-                pieces.push(TemplatePiece::NamedSection(
-                    Cow::Borrowed(&i["# header ".len()..]),
-                    vec![],
-                ));
+                pieces.push(TemplatePiece::NamedSection(Cow::Borrowed(header), vec![]));
             } else {
                 current_template.push(Cow::Borrowed(i));
             }
         }
         pieces.push(TemplatePiece::Section(current_template));
         Self {
+            origins: vec![Vec::new(); pieces.len()],
             pieces,
             current_code_section: Cow::Borrowed("CODE"),
+            origin: None,
         }
     }
 
@@ -47,7 +55,7 @@ impl<'a> Template<'a> {
                 }
             }
         }
-        return None;
+        None
     }
 
     pub fn set_code_section(&mut self, section: Cow<'a, str>) {
@@ -55,12 +63,13 @@ impl<'a> Template<'a> {
     }
 
     pub fn add_code(&mut self, string: Cow<'a, str>) {
-        for i in self.pieces.iter_mut() {
+        for (i, origins) in self.pieces.iter_mut().zip(&mut self.origins) {
             match i {
                 TemplatePiece::Section(_) => (),
                 TemplatePiece::NamedSection(a, b) => {
                     if a == &self.current_code_section {
                         b.push(string);
+                        origins.push(self.origin.clone());
                         break;
                     }
                 }
@@ -69,12 +78,13 @@ impl<'a> Template<'a> {
     }
 
     pub fn add_section(&mut self, section: &'a str, string: Cow<'a, str>) {
-        for i in self.pieces.iter_mut() {
+        for (i, origins) in self.pieces.iter_mut().zip(&mut self.origins) {
             match i {
                 TemplatePiece::Section(_) => (),
                 TemplatePiece::NamedSection(a, b) => {
                     if a == section {
                         b.push(string);
+                        origins.push(self.origin.clone());
                         break;
                     }
                 }
@@ -92,6 +102,36 @@ impl<'a> Template<'a> {
             .join("\n")
     }
 
+    /// Origin of every line of [`build`](Self::build), as recorded when the code was added.
+    pub fn build_source_map(&self) -> SourceMap {
+        let mut lines = Vec::new();
+        for (piece, origins) in self.pieces.iter().zip(&self.origins) {
+            let (TemplatePiece::Section(strings) | TemplatePiece::NamedSection(_, strings)) = piece;
+            if strings.is_empty() {
+                lines.push(None);
+            }
+            for (i, string) in strings.iter().enumerate() {
+                let origin = origins.get(i).cloned().flatten();
+                lines.extend(string.split('\n').map(|_| origin.clone()));
+            }
+        }
+        SourceMap { lines }
+    }
+
+    /// Builds only the named sections, each preceded by its `# header` line.
+    pub fn build_sections(&self) -> String {
+        self.pieces
+            .iter()
+            .filter_map(|x| match x {
+                TemplatePiece::Section(_) => None,
+                TemplatePiece::NamedSection(a, b) => {
+                    Some(format!("# header {}\n{}", a, b.join("\n")))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
     pub fn apply(&mut self, a: &impl Instruction) {
         a.apply(self)
     }
@@ -0,0 +1,309 @@
+//! Interpreter for the Cythan machine the template targets.
+//!
+//! The memory is a list of cells, cell 0 being the instruction pointer. Every cycle reads the two
+//! cells `a` and `b` it points to, moves the pointer two cells forward and then copies the cell at
+//! address `a` to the cell at address `b`. Cells that were never written read as 0.
+//!
+//! Numbers follow the conventions of `template.ct`: a number is a cell holding 1 to 16, 16 standing
+//! for 0, and `exit` copies its value to `'#return_0` before running `stop`, a pair of cells
+//! jumping back to itself forever.
+
+use std::fmt::Display;
+
+use crate::{assembler::Image, SourceMap};
+
+/// Cycles run by [`Machine::run`] callers that do not pick a limit.
+pub const DEFAULT_MAX_CYCLES: u64 = 10_000_000;
+
+/// Highest address a program may write to, so that a corrupted pointer cannot exhaust memory.
+const MAX_ADDRESS: usize = 1 << 20;
+
+/// Reason a run ended without reaching `stop`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunError {
+    /// The program was still running after the given number of cycles.
+    CycleLimit(u64),
+    /// A cycle tried to run the instruction at, or write to, a cell past [`MAX_ADDRESS`].
+    OutOfMemory { address: usize, cycle: u64 },
+    /// The program stopped in the trap of a checked operation, assembled from the given line of
+    /// the template output.
+    Overflow { line: usize },
+    /// The program stopped on a call deeper than the call stack of its function, assembled from
+    /// the given line of the template output.
+    StackOverflow { line: usize },
+}
+
+impl RunError {
+    /// The error, a trap being located with `map`, which maps the lines of the template output
+    /// to the file the program was written in.
+    pub fn describe(&self, map: &SourceMap) -> String {
+        match self {
+            RunError::Overflow { line } => match map.origin(*line) {
+                Some(e) => format!("arithmetic overflow at {}", e),
+                None => self.to_string(),
+            },
+            RunError::StackOverflow { line } => match map.origin(*line) {
+                Some(e) => format!("stack overflow at {}", e),
+                None => self.to_string(),
+            },
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::CycleLimit(e) => write!(f, "the program did not stop after {} cycles", e),
+            RunError::OutOfMemory { address, cycle } => write!(
+                f,
+                "cycle {} uses address {}, past the end of memory",
+                cycle, address
+            ),
+            RunError::Overflow { line } => write!(
+                f,
+                "arithmetic overflow at line {} of the template output",
+                line
+            ),
+            RunError::StackOverflow { line } => {
+                write!(f, "stack overflow at line {} of the template output", line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+/// A Cythan machine loaded with a program.
+pub struct Machine {
+    pub memory: Vec<usize>,
+    /// Cycles run so far.
+    pub cycles: u64,
+    /// Address of `'#return_0`, where `exit` writes its value.
+    return_cell: Option<usize>,
+    /// Address of `'#exit_signed`, set by `exit_signed` to read the value as a signed number.
+    signed_cell: Option<usize>,
+    /// Address of `'#overflow`, set by the trap of checked operations.
+    overflow_cell: Option<usize>,
+    /// Address of `'#stack_overflow`, set by calls deeper than a call stack.
+    stack_overflow_cell: Option<usize>,
+}
+
+impl Machine {
+    pub fn new(image: &Image) -> Self {
+        Self {
+            memory: image.memory.clone(),
+            cycles: 0,
+            return_cell: image.label("#return_0"),
+            signed_cell: image.label("#exit_signed"),
+            overflow_cell: image.label("#overflow"),
+            stack_overflow_cell: image.label("#stack_overflow"),
+        }
+    }
+
+    /// Value of the cell at `address`.
+    pub fn get(&self, address: usize) -> usize {
+        self.memory.get(address).copied().unwrap_or(0)
+    }
+
+    fn set(&mut self, address: usize, value: usize) -> Result<(), RunError> {
+        if address > MAX_ADDRESS {
+            return Err(RunError::OutOfMemory {
+                address,
+                cycle: self.cycles,
+            });
+        }
+        if address >= self.memory.len() {
+            self.memory.resize(address + 1, 0);
+        }
+        self.memory[address] = value;
+        Ok(())
+    }
+
+    /// Runs one cycle and tells whether the machine is stopped, which is the case when the cycle
+    /// left the instruction pointer where it was.
+    pub fn step(&mut self) -> Result<bool, RunError> {
+        let pointer = self.get(0);
+        if pointer >= MAX_ADDRESS {
+            return Err(RunError::OutOfMemory {
+                address: pointer,
+                cycle: self.cycles,
+            });
+        }
+        let (from, to) = (self.get(pointer), self.get(pointer + 1));
+        self.cycles += 1;
+        self.set(0, pointer + 2)?;
+        self.set(to, self.get(from))?;
+        Ok(self.get(0) == pointer)
+    }
+
+    /// Runs the program until it stops, giving up after `max_cycles` cycles.
+    pub fn run(&mut self, max_cycles: u64) -> Result<(), RunError> {
+        while self.cycles < max_cycles {
+            if self.step()? {
+                return Ok(());
+            }
+        }
+        Err(RunError::CycleLimit(self.cycles))
+    }
+
+    /// The number the program exited with, if it has a `'#return_0` cell holding one: from 0 to
+    /// 15, or from -8 to 7 when it exited with `exit_signed`.
+    pub fn exit_value(&self) -> Option<i8> {
+        let value = as_number(self.get(self.return_cell?))? as i8;
+        let signed = self.signed_cell.map(|x| self.get(x)) == Some(1);
+        Some(if signed && value >= 8 {
+            value - 16
+        } else {
+            value
+        })
+    }
+
+    /// Whether the program stopped in the trap of a checked operation.
+    pub fn overflowed(&self) -> bool {
+        self.overflow_cell.map(|x| self.get(x)) == Some(1)
+    }
+
+    /// Whether the program stopped on a call deeper than the call stack of its function.
+    pub fn stack_overflowed(&self) -> bool {
+        self.stack_overflow_cell.map(|x| self.get(x)) == Some(1)
+    }
+}
+
+/// The number (0 to 15) a cell holding 1 to 16 stands for.
+pub fn as_number(cell: usize) -> Option<u8> {
+    match cell {
+        1..=16 => Some((cell % 16) as u8),
+        _ => None,
+    }
+}
+
+/// Runs `image` until it stops and returns its exit value.
+pub fn run(image: &Image, max_cycles: u64) -> Result<Option<i8>, RunError> {
+    let mut machine = Machine::new(image);
+    machine.run(max_cycles)?;
+    let line = image.lines.get(machine.get(0)).copied().unwrap_or(0);
+    if machine.overflowed() {
+        return Err(RunError::Overflow { line });
+    }
+    if machine.stack_overflowed() {
+        return Err(RunError::StackOverflow { line });
+    }
+    Ok(machine.exit_value())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// An image made of `memory`, cell `n` coming from line `n + 1`, with the given labels.
+    fn image(memory: &[usize], labels: &[(&str, usize)]) -> Image {
+        Image {
+            memory: memory.to_vec(),
+            labels: labels
+                .iter()
+                .map(|(a, b)| (a.to_string(), *b))
+                .collect::<HashMap<_, _>>(),
+            lines: (1..=memory.len()).collect(),
+        }
+    }
+
+    /// A program stopping right away on the pair at 2, which copies the 2 at cell 4 to the
+    /// instruction pointer, followed by the cell `exit` writes to.
+    fn stopped(value: usize) -> Vec<usize> {
+        vec![2, 0, 4, 0, 2, value]
+    }
+
+    #[test]
+    fn stops_on_a_self_loop() {
+        let mut machine = Machine::new(&image(&stopped(0), &[]));
+        assert_eq!(machine.step(), Ok(true));
+        assert_eq!(machine.get(0), 2);
+        assert_eq!(machine.run(100), Ok(()));
+        assert_eq!(machine.cycles, 2);
+    }
+
+    #[test]
+    fn gives_up_after_the_cycle_limit() {
+        // The pairs at 2 and 4 jump to each other forever.
+        let image = image(&[2, 0, 6, 0, 7, 0, 4, 2], &[]);
+        let mut machine = Machine::new(&image);
+        assert_eq!(machine.step(), Ok(false));
+        assert_eq!(machine.get(0), 4);
+        assert_eq!(run(&image, 50), Err(RunError::CycleLimit(50)));
+    }
+
+    #[test]
+    fn reports_writes_past_the_end_of_memory() {
+        let image = image(&[2, 0, 0, MAX_ADDRESS + 1], &[]);
+        assert_eq!(
+            run(&image, 10),
+            Err(RunError::OutOfMemory {
+                address: MAX_ADDRESS + 1,
+                cycle: 1
+            })
+        );
+    }
+
+    #[test]
+    fn reports_pointers_past_the_end_of_memory() {
+        for pointer in [MAX_ADDRESS, usize::MAX - 1, usize::MAX] {
+            assert_eq!(
+                run(&image(&[pointer], &[]), 10),
+                Err(RunError::OutOfMemory {
+                    address: pointer,
+                    cycle: 0
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn reads_unsigned_exit_values() {
+        let labels = [("#return_0", 5)];
+        assert_eq!(run(&image(&stopped(11), &labels), 10), Ok(Some(11)));
+        assert_eq!(run(&image(&stopped(16), &labels), 10), Ok(Some(0)));
+        assert_eq!(run(&image(&stopped(12), &labels), 10), Ok(Some(12)));
+        assert_eq!(run(&image(&stopped(0), &labels), 10), Ok(None));
+        assert_eq!(run(&image(&stopped(12), &[]), 10), Ok(None));
+    }
+
+    #[test]
+    fn reads_signed_exit_values() {
+        let labels = [("#return_0", 5), ("#exit_signed", 6)];
+        let signed = |value, flag| {
+            let mut memory = stopped(value);
+            memory.push(flag);
+            run(&image(&memory, &labels), 10)
+        };
+        assert_eq!(signed(12, 1), Ok(Some(-4)));
+        assert_eq!(signed(8, 1), Ok(Some(-8)));
+        assert_eq!(signed(7, 1), Ok(Some(7)));
+        assert_eq!(signed(12, 0), Ok(Some(12)));
+    }
+
+    #[test]
+    fn reports_overflow_traps() {
+        let labels = [("#return_0", 5), ("#overflow", 6)];
+        let mut memory = stopped(3);
+        memory.push(1);
+        assert_eq!(
+            run(&image(&memory, &labels), 10),
+            Err(RunError::Overflow { line: 3 })
+        );
+        memory[6] = 0;
+        assert_eq!(run(&image(&memory, &labels), 10), Ok(Some(3)));
+    }
+
+    #[test]
+    fn reports_stack_overflow_traps() {
+        let mut memory = stopped(3);
+        memory.push(1);
+        assert_eq!(
+            run(&image(&memory, &[("#stack_overflow", 6)]), 10),
+            Err(RunError::StackOverflow { line: 3 })
+        );
+    }
+}
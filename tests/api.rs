@@ -0,0 +1,50 @@
+//! The public API of `cythanc`, used the way other crates do.
+
+use cythanc::{
+    assembler::assemble, compile_str, vm, Diagnostic, Error, Origin, SourceMap, DEFAULT_TEMPLATE,
+};
+
+#[test]
+fn compiles_and_runs_ir() {
+    let output = compile_str("let a 2\ninc a\nexit a", DEFAULT_TEMPLATE).unwrap();
+    assert!(output.contains("inc('var_a)"));
+    let image = assemble("<output>", &output).unwrap();
+    assert_eq!(vm::run(&image, vm::DEFAULT_MAX_CYCLES), Ok(Some(3)));
+}
+
+#[test]
+fn reports_every_error() {
+    let error = compile_str("frobnicate a\nlet b 1\nfrobnicate b", DEFAULT_TEMPLATE).unwrap_err();
+    assert_eq!(error.error_count(), 2);
+    assert_eq!(error.diagnostics[0].line, 1);
+    assert_eq!(error.diagnostics[1].line, 3);
+}
+
+#[test]
+fn counts_errors_only() {
+    let error = Error {
+        diagnostics: vec![
+            Diagnostic::error("bad").in_file("a.ct", 1),
+            Diagnostic::warning("odd").in_file("a.ct", 2),
+            Diagnostic::note("see here").in_file("a.ct", 3),
+        ],
+    };
+    assert_eq!(error.error_count(), 1);
+    let text = error.to_string();
+    assert!(text.starts_with("error: bad\n"));
+    assert!(text.contains("\n\nwarning: odd\n"));
+    assert!(text.contains("\n\nnote: see here\n"));
+}
+
+#[test]
+fn locates_assembly_errors_in_the_source() {
+    let map = SourceMap {
+        lines: vec![None, Some(Origin::new("in.ct", 4))],
+    };
+    let error = match assemble("<output>", "0\n'nowhere") {
+        Ok(_) => panic!("an undefined label assembled"),
+        Err(e) => e.locate(&[&map]),
+    };
+    assert_eq!(error.diagnostics[0].line, 2);
+    assert!(error.to_string().contains("= note: generated from in.ct:4"));
+}